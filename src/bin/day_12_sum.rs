@@ -0,0 +1,36 @@
+//! Sums the numbers in a day 12 JSON document of any size, reading it a chunk at a time.
+//!
+//! Usage: `cargo run --bin day_12_sum -- [<document>] [--skip-red]`
+//!
+//! Reads the document from standard input if no path is given.
+
+use std::fs::File;
+use std::io;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut path = None;
+    let mut skip_red = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--skip-red" => skip_red = true,
+            _ => path = Some(arg),
+        }
+    }
+    let result = match path {
+        Some(path) => File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| advent_of_code_2015::sum_day_12_stream(file, skip_red)),
+        None => advent_of_code_2015::sum_day_12_stream(io::stdin().lock(), skip_red),
+    };
+    match result {
+        Ok(sum) => {
+            println!("{sum}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
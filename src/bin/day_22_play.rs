@@ -0,0 +1,28 @@
+//! Plays the day 22 wizard battle interactively.
+//!
+//! Usage: `cargo run --bin day_22_play -- <puzzle input> [--hard] [--hints]`
+
+use std::io;
+
+fn main() -> io::Result<()> {
+    let mut path = None;
+    let mut hard_mode = false;
+    let mut hints = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--hard" => hard_mode = true,
+            "--hints" => hints = true,
+            _ => path = Some(arg),
+        }
+    }
+    let path = path
+        .ok_or_else(|| io::Error::other("usage: day_22_play <puzzle input> [--hard] [--hints]"))?;
+    let puzzle_input = std::fs::read_to_string(path)?;
+    advent_of_code_2015::play_day_22(
+        &puzzle_input,
+        hard_mode,
+        hints,
+        io::stdin().lock(),
+        io::stdout().lock(),
+    )
+}
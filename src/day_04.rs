@@ -1,31 +1,105 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::thread;
+
+/// How many nonces a worker of the parallel search claims at a time.
+const BLOCK_SIZE: u32 = 1 << 14;
+
 #[aoc(day4, part1)]
 fn part_1(input: &[u8]) -> u32 {
-    let mut root = md5::Context::new();
-    root.consume(input.trim_ascii());
-    for x in 1_u32.. {
-        let mut ctx = root.clone();
-        ctx.consume(format!("{x}"));
-        let hash = ctx.finalize().0;
-        if matches!(hash, [0, 0, b, ..] if b >> 4 == 0) {
-            return x;
-        }
-    }
-    0
+    mine(input.trim_ascii(), 5).unwrap_or(0)
 }
 
 #[aoc(day4, part2)]
 fn part_2(input: &[u8]) -> u32 {
+    mine(input.trim_ascii(), 6).unwrap_or(0)
+}
+
+#[aoc(day4, part1, parallel)]
+fn part_1_parallel(input: &[u8]) -> u32 {
+    mine_parallel(input.trim_ascii(), 5, available_workers()).unwrap_or(0)
+}
+
+#[aoc(day4, part2, parallel)]
+fn part_2_parallel(input: &[u8]) -> u32 {
+    mine_parallel(input.trim_ascii(), 6, available_workers()).unwrap_or(0)
+}
+
+fn available_workers() -> usize {
+    thread::available_parallelism().map_or(1, usize::from)
+}
+
+/// Finds the smallest positive nonce whose hash with `key` starts with `zeros` zero hex
+/// digits.
+fn mine(key: &[u8], zeros: usize) -> Option<u32> {
     let mut root = md5::Context::new();
-    root.consume(input.trim_ascii());
-    for x in 1_u32.. {
-        let mut ctx = root.clone();
-        ctx.consume(format!("{x}"));
-        let hash = ctx.finalize().0;
-        if matches!(hash, [0, 0, 0, ..]) {
-            return x;
+    root.consume(key);
+    (1..=u32::MAX).find(|&nonce| is_coin(&root, nonce, zeros))
+}
+
+/// Finds the same nonce as [`mine`], searching with several threads.
+///
+/// The workers claim blocks of nonces in increasing order, and search each block from the
+/// start. The first hit in a block is recorded if it is the smallest so far, and the
+/// workers stop at any nonce past it. Every block before the smallest hit is still searched
+/// in full, so the result does not depend on how the threads are scheduled.
+fn mine_parallel(key: &[u8], zeros: usize, workers: usize) -> Option<u32> {
+    let mut root = md5::Context::new();
+    root.consume(key);
+    let next_block = AtomicU32::new(0);
+    // The smallest nonce found so far, or `u64::MAX` if none.
+    let best = AtomicU64::new(u64::MAX);
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| {
+                loop {
+                    let block = next_block.fetch_add(1, Ordering::Relaxed);
+                    let Ok(first) = u32::try_from(u64::from(block) * u64::from(BLOCK_SIZE)) else {
+                        break;
+                    };
+                    // Blocks are claimed in order, so all later blocks are past the best too.
+                    if u64::from(first) >= best.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    for nonce in first.max(1)..=first.saturating_add(BLOCK_SIZE - 1) {
+                        if u64::from(nonce) >= best.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        if is_coin(&root, nonce, zeros) {
+                            best.fetch_min(u64::from(nonce), Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    u32::try_from(best.into_inner()).ok()
+}
+
+fn is_coin(root: &md5::Context, nonce: u32, zeros: usize) -> bool {
+    let mut buffer = [0; 10];
+    let mut context = root.clone();
+    context.consume(decimal(nonce, &mut buffer));
+    has_leading_zeros(&context.finalize().0, zeros)
+}
+
+/// Writes `number` in decimal to the end of `buffer`, returning the digits.
+fn decimal(mut number: u32, buffer: &mut [u8; 10]) -> &[u8] {
+    let mut start = buffer.len();
+    loop {
+        start -= 1;
+        buffer[start] = b'0' + (number % 10) as u8;
+        number /= 10;
+        if number == 0 {
+            return &buffer[start..];
         }
     }
-    0
+}
+
+/// Whether the hash starts with `zeros` zero hex digits.
+fn has_leading_zeros(hash: &[u8; 16], zeros: usize) -> bool {
+    hash[..zeros / 2].iter().all(|&byte| byte == 0)
+        && (zeros.is_multiple_of(2) || hash[zeros / 2] >> 4 == 0)
 }
 
 #[cfg(test)]
@@ -44,4 +118,47 @@ mod tests {
     fn test_part_2(input: &[u8]) -> u32 {
         part_2(input)
     }
+
+    #[test_case(b"abcdef" => 609_043)]
+    #[test_case(b"pqrstuv" => 1_048_970)]
+    fn test_part_1_parallel(input: &[u8]) -> u32 {
+        part_1_parallel(input)
+    }
+
+    #[test_case(b"abcdef" => 6_742_839)]
+    #[test_case(b"pqrstuv" => 5_714_438)]
+    fn test_part_2_parallel(input: &[u8]) -> u32 {
+        part_2_parallel(input)
+    }
+
+    #[test]
+    fn test_mine_parallel_is_deterministic() {
+        for key in [b"abcdef".as_slice(), b"pqrstuv", b"iwrupvqb"] {
+            for zeros in 1..=4 {
+                let expected = mine(key, zeros);
+                for workers in [1, 2, 3, 8] {
+                    assert_eq!(mine_parallel(key, zeros, workers), expected);
+                }
+            }
+        }
+    }
+
+    #[test_case(0 => b"0".to_vec())]
+    #[test_case(7 => b"7".to_vec())]
+    #[test_case(609_043 => b"609043".to_vec())]
+    #[test_case(u32::MAX => b"4294967295".to_vec())]
+    fn test_decimal(number: u32) -> Vec<u8> {
+        decimal(number, &mut [0; 10]).to_vec()
+    }
+
+    #[test_case(&[0, 0, 0x0f, 0xff], 5 => true)]
+    #[test_case(&[0, 0, 0x10, 0], 5 => false)]
+    #[test_case(&[0, 0, 0x0f, 0], 6 => false)]
+    #[test_case(&[0, 0, 0, 0xff], 6 => true)]
+    #[test_case(&[0xff, 0, 0, 0], 0 => true)]
+    fn test_has_leading_zeros(start: &[u8], zeros: usize) -> bool {
+        let mut hash = [0xff; 16];
+        hash[..start.len()].copy_from_slice(start);
+        has_leading_zeros(&hash, zeros)
+    }
 }
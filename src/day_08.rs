@@ -1,45 +1,145 @@
-#[derive(Debug, Clone, Copy)]
-enum State {
-    Normal,
-    Escaped,
-    Hex0,
-    Hex1,
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{error} on line {line}")]
+struct ParseError {
+    line: usize,
+    #[source]
+    error: DecodeError,
 }
 
-#[aoc(day8, part1)]
-fn part_1(input: &[u8]) -> usize {
-    let mut len = 0;
-    for line in input.split(|&ch| ch == b'\n') {
-        let mut state = State::Normal;
-        len += line.len();
-        for &ch in &line[1..line.len() - 1] {
-            state = match (state, ch) {
-                (State::Normal, b'\\') => State::Escaped,
-                (State::Escaped, b'x' | b'X') => State::Hex0,
-                (State::Hex0, _) => State::Hex1,
-                _ => {
-                    len -= 1;
-                    State::Normal
-                }
-            };
+#[derive(Debug, Error, PartialEq, Eq)]
+enum DecodeError {
+    #[error("Empty literal")]
+    Empty,
+    #[error("Missing opening quote")]
+    MissingOpeningQuote,
+    #[error("Missing closing quote")]
+    MissingClosingQuote,
+    #[error("Unescaped quote at byte {0}")]
+    UnescapedQuote(usize),
+    #[error("Unknown escape sequence \\{found} at byte {position}")]
+    UnknownEscape { position: usize, found: char },
+    #[error("Invalid hex escape at byte {0}")]
+    InvalidHexEscape(usize),
+}
+
+/// Decodes a quoted string literal into the bytes it represents.
+///
+/// The literal may contain the escape sequences `\\`, `\"` and `\xHH`.
+fn decode(literal: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    match literal.first() {
+        None => return Err(DecodeError::Empty),
+        Some(b'"') => {}
+        Some(_) => return Err(DecodeError::MissingOpeningQuote),
+    }
+    let mut bytes = Vec::with_capacity(literal.len());
+    let mut position = 1;
+    while let Some(&ch) = literal.get(position) {
+        match ch {
+            b'"' if position + 1 == literal.len() => return Ok(bytes),
+            b'"' => return Err(DecodeError::UnescapedQuote(position)),
+            b'\\' => {
+                let (byte, len) = decode_escape(literal, position)?;
+                bytes.push(byte);
+                position += len;
+            }
+            _ => {
+                bytes.push(ch);
+                position += 1;
+            }
         }
     }
-    len
+    Err(DecodeError::MissingClosingQuote)
 }
 
-#[aoc(day8, part2)]
-fn part_2(input: &[u8]) -> usize {
-    let mut len = 0;
-    for line in input.split(|&ch| ch == b'\n') {
-        for &ch in line {
-            len += match ch {
-                b'\\' | b'"' => 1,
-                _ => 0,
-            };
+/// Decodes the escape sequence starting with the backslash at `position`, returning the
+/// byte and the length of the sequence.
+fn decode_escape(literal: &[u8], position: usize) -> Result<(u8, usize), DecodeError> {
+    match literal.get(position + 1) {
+        None => Err(DecodeError::MissingClosingQuote),
+        Some(&ch @ (b'\\' | b'"')) => Ok((ch, 2)),
+        Some(b'x' | b'X') => {
+            let digits = literal
+                .get(position + 2..position + 4)
+                .ok_or(DecodeError::InvalidHexEscape(position))?;
+            let value = digits.iter().try_fold(0_u8, |value, &digit| {
+                let digit = u8::try_from(char::from(digit).to_digit(16)?).ok()?;
+                Some(value * 16 + digit)
+            });
+            value
+                .map(|value| (value, 4))
+                .ok_or(DecodeError::InvalidHexEscape(position))
+        }
+        Some(&ch) => Err(DecodeError::UnknownEscape {
+            position,
+            found: char::from(ch),
+        }),
+    }
+}
+
+/// Encodes arbitrary bytes as a quoted string literal that [`decode`] turns back into
+/// them. As in part 2, only quotes and backslashes are escaped, and every other byte is
+/// kept as it is.
+fn encode(bytes: &[u8]) -> Vec<u8> {
+    let mut literal = Vec::with_capacity(bytes.len() + 2);
+    literal.push(b'"');
+    for &byte in bytes {
+        if let b'"' | b'\\' = byte {
+            literal.push(b'\\');
         }
-        len += 2;
+        literal.push(byte);
+    }
+    literal.push(b'"');
+    literal
+}
+
+/// A string literal as written in the file, and the bytes it represents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Literal {
+    code: Vec<u8>,
+    bytes: Vec<u8>,
+}
+
+/// Parses one literal per line. Lines may end in `\n` or `\r\n`, and the last one may
+/// end the file without a line ending.
+#[aoc_generator(day8)]
+fn parse(input: &[u8]) -> Result<Vec<Literal>, ParseError> {
+    let input = input.strip_suffix(b"\n").unwrap_or(input);
+    if input.is_empty() {
+        return Ok(Vec::new());
     }
-    len
+    input
+        .split(|&ch| ch == b'\n')
+        .enumerate()
+        .map(|(index, line)| {
+            let code = line.strip_suffix(b"\r").unwrap_or(line);
+            let bytes = decode(code).map_err(|error| ParseError {
+                line: index + 1,
+                error,
+            })?;
+            Ok(Literal {
+                code: code.to_vec(),
+                bytes,
+            })
+        })
+        .collect()
+}
+
+#[aoc(day8, part1)]
+fn part_1(input: &[Literal]) -> usize {
+    input
+        .iter()
+        .map(|literal| literal.code.len() - literal.bytes.len())
+        .sum()
+}
+
+#[aoc(day8, part2)]
+fn part_2(input: &[Literal]) -> usize {
+    input
+        .iter()
+        .map(|literal| encode(&literal.code).len() - literal.code.len())
+        .sum()
 }
 
 #[cfg(test)]
@@ -47,19 +147,74 @@ mod tests {
     use super::*;
     use test_case::test_case;
 
+    #[test_case(br#""""# => Ok(b"".to_vec()))]
+    #[test_case(br#""abc""# => Ok(b"abc".to_vec()))]
+    #[test_case(br#""abc\"abc""# => Ok(b"abc\"abc".to_vec()))]
+    #[test_case(br#""\x27\\\xfF""# => Ok(b"'\\\xff".to_vec()))]
+    #[test_case(b"" => Err(DecodeError::Empty))]
+    #[test_case(br"abc" => Err(DecodeError::MissingOpeningQuote))]
+    #[test_case(br#""abc"# => Err(DecodeError::MissingClosingQuote))]
+    #[test_case(br#""abc\""# => Err(DecodeError::MissingClosingQuote); "escaped closing quote")]
+    #[test_case(br#"""# => Err(DecodeError::MissingClosingQuote); "lone quote")]
+    #[test_case(br#""ab"c""# => Err(DecodeError::UnescapedQuote(3)))]
+    #[test_case(br#""a\n""# => Err(DecodeError::UnknownEscape { position: 2, found: 'n' }))]
+    #[test_case(br#""a\xZZ""# => Err(DecodeError::InvalidHexEscape(2)))]
+    #[test_case(br#""a\x2""# => Err(DecodeError::InvalidHexEscape(2)); "short hex escape")]
+    fn test_decode(literal: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        decode(literal)
+    }
+
+    #[test_case(b"" => br#""""#.to_vec())]
+    #[test_case(b"abc" => br#""abc""#.to_vec())]
+    #[test_case(br#""\x27""# => br#""\"\\x27\"""#.to_vec())]
+    #[test_case(b"\t\xff" => b"\"\t\xff\"".to_vec())]
+    fn test_encode(bytes: &[u8]) -> Vec<u8> {
+        encode(bytes)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..=u8::MAX).collect();
+        assert_eq!(decode(&encode(&bytes)), Ok(bytes));
+        for literal in parse(EXAMPLE).unwrap() {
+            assert_eq!(decode(&encode(&literal.code)), Ok(literal.code));
+        }
+    }
+
+    const EXAMPLE: &[u8] = br#"""
+"abc"
+"aaa\"aaa"
+"\x27"
+"#;
+
+    #[test_case(EXAMPLE => Ok(4); "trailing newline")]
+    #[test_case(b"\"a\"\r\n\"b\"\r\n" => Ok(2); "crlf")]
+    #[test_case(b"\"a\"\n\"b\"" => Ok(2); "no trailing newline")]
+    #[test_case(b"" => Ok(0); "empty file")]
+    #[test_case(b"\"a\"\n\n\"b\"" => Err(ParseError { line: 2, error: DecodeError::Empty }))]
+    #[test_case(b"\"a\"\n\"b\"\n\n" => Err(ParseError { line: 3, error: DecodeError::Empty }); "two trailing newlines")]
+    #[test_case(b"\"a\"\r\n\"b\r\n" => Err(ParseError { line: 2, error: DecodeError::MissingClosingQuote }); "crlf unterminated")]
+    #[test_case(b"\"a\" \n" => Err(ParseError { line: 1, error: DecodeError::UnescapedQuote(2) }); "trailing space")]
+    fn test_parse(input: &[u8]) -> Result<usize, ParseError> {
+        parse(input).map(|literals| literals.len())
+    }
+
     #[test_case(br#""""# => 2)]
     #[test_case(br#""abc""# => 2)]
     #[test_case(br#""abc\"abc""# => 3)]
     #[test_case(br#""\x27""# => 5)]
+    #[test_case(EXAMPLE => 12; "example")]
     fn test_part_1(input: &[u8]) -> usize {
-        part_1(input)
+        part_1(&parse(input).unwrap())
     }
 
     #[test_case(br#""""# => 4)]
     #[test_case(br#""abc""# => 4)]
     #[test_case(br#""abc\"abc""# => 6)]
     #[test_case(br#""\x27""# => 5)]
+    #[test_case(EXAMPLE => 19; "example")]
+    #[test_case(b"\"a\tb\xc3\xa9\\x41\"" => 5; "tab and non-ascii")]
     fn test_part_2(input: &[u8]) -> usize {
-        part_2(input)
+        part_2(&parse(input).unwrap())
     }
 }
@@ -1,159 +1,1005 @@
-use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::io::{self, Read};
+use std::iter::Sum;
+use std::num::ParseIntError;
+use std::ops::Add;
+use std::str::FromStr;
 
-#[aoc(day12, part1)]
-fn part_1(input: &[u8]) -> i64 {
-    let mut sum = 0;
-    lex(input, |token| {
-        if let Token::Integer(num) = token {
-            sum += num;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{kind} at line {line}, column {column}")]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum ParseErrorKind {
+    #[error("Unexpected end of input")]
+    UnexpectedEnd,
+    #[error("Unexpected character {0:?}")]
+    UnexpectedCharacter(char),
+    #[error("Invalid escape sequence")]
+    InvalidEscape,
+    #[error("Unescaped control character in string")]
+    ControlCharacter,
+    #[error("Invalid UTF-8")]
+    InvalidUtf8,
+    #[error("Invalid number")]
+    InvalidNumber,
+    #[error("Nested deeper than {MAX_DEPTH} levels")]
+    TooDeep,
+}
+
+/// How deeply arrays and objects can be nested, to keep the parser's recursion bounded.
+const MAX_DEPTH: usize = 512;
+
+/// A JSON number. Integers are kept exact, unless they get too large for `i64`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+impl Add for Number {
+    type Output = Self;
+
+    #[allow(clippy::cast_precision_loss, reason = "Floats are already approximate")]
+    fn add(self, rhs: Self) -> Self {
+        match (self, rhs) {
+            (Self::Integer(a), Self::Integer(b)) => a
+                .checked_add(b)
+                .map_or_else(|| Self::Float(a as f64 + b as f64), Self::Integer),
+            (Self::Integer(a), Self::Float(b)) | (Self::Float(b), Self::Integer(a)) => {
+                Self::Float(a as f64 + b)
+            }
+            (Self::Float(a), Self::Float(b)) => Self::Float(a + b),
+        }
+    }
+}
+
+impl Sum for Number {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::Integer(0), |s, n| s + n)
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::Float(n) => write!(f, "{n}"),
         }
-    });
-    sum
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Self>),
+    /// The members in the order they appear, including any repeated keys.
+    Object(Vec<(String, Self)>),
+}
+
+impl Value {
+    /// Folds the tree from the leaves up. `f` is called for every value, with what its
+    /// elements or member values folded to.
+    fn fold<T>(&self, f: &mut impl FnMut(&Self, Vec<T>) -> T) -> T {
+        let children = match self {
+            Self::Array(elements) => elements.iter().map(|element| element.fold(f)).collect(),
+            Self::Object(members) => members.iter().map(|(_, value)| value.fold(f)).collect(),
+            _ => Vec::new(),
+        };
+        f(self, children)
+    }
+}
+
+#[aoc_generator(day12)]
+fn parse(input: &[u8]) -> Result<Value, ParseError> {
+    let mut parser = Parser { input, position: 0 };
+    parser.document().map_err(|kind| parser.error(kind))
+}
+
+#[aoc(day12, part1)]
+fn part_1(input: &Value) -> Number {
+    input.fold(&mut |value, children| match value {
+        &Value::Number(number) => number,
+        _ => children.into_iter().sum(),
+    })
 }
 
 #[aoc(day12, part2)]
-fn part_2(input: &[u8]) -> i64 {
-    let mut context = Vec::new();
-    let mut current = Context::Array;
-    let mut sum = 0;
-    lex(input, |token| match token {
-        Token::Integer(val) => {
-            if let Context::Object(ref mut octx) = current {
-                octx.expects_value = !octx.expects_value;
-            }
-            sum += val;
-        }
-        Token::String(s) => {
-            if let Context::Object(ref mut octx) = current {
-                if octx.expects_value && s == "red" {
-                    octx.rollback_sum = true;
-                }
-                octx.expects_value = !octx.expects_value;
+fn part_2(input: &Value) -> Result<Number, FilterError> {
+    Ok(filtered_sum(input, &[PART_2_FILTER.parse()?]))
+}
+
+#[aoc(day12, part2, paths)]
+fn part_2_paths(input: &Value) -> Result<String, FilterError> {
+    let filters = [PART_2_FILTER.parse()?];
+    let mut output = String::new();
+    for PathSum { path, depth, sum } in sums_by_path(input, &filters) {
+        if depth <= 1 {
+            let _ = write!(output, "\n{path}: {sum}");
+        }
+    }
+    Ok(output)
+}
+
+/// The filter for part 2, in the syntax of [`Filter::from_str`].
+const PART_2_FILTER: &str = r#"value="red""#;
+
+/// Arrays and objects to leave out of a sum, along with everything inside them.
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    /// Objects with a property whose value equals this.
+    PropertyValue(Value),
+    /// Objects with this key.
+    HasKey(String),
+    /// Arrays and objects nested deeper than this. The document is at depth 0.
+    DeeperThan(usize),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum FilterError {
+    #[error("Unknown filter: {0}")]
+    UnknownFilter(String),
+    #[error(transparent)]
+    InvalidValue(#[from] ParseError),
+    #[error(transparent)]
+    InvalidDepth(#[from] ParseIntError),
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    /// Parses `value=<JSON value>`, `key=<key>` or `depth><depth>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(value) = s.strip_prefix("value=") {
+            Ok(Self::PropertyValue(parse(value.as_bytes())?))
+        } else if let Some(key) = s.strip_prefix("key=") {
+            Ok(Self::HasKey(key.to_string()))
+        } else if let Some(depth) = s.strip_prefix("depth>") {
+            Ok(Self::DeeperThan(depth.parse()?))
+        } else {
+            Err(FilterError::UnknownFilter(s.to_string()))
+        }
+    }
+}
+
+impl Filter {
+    fn matches(&self, value: &Value, depth: usize) -> bool {
+        match (self, value) {
+            (Self::PropertyValue(expected), Value::Object(members)) => {
+                members.iter().any(|(_, value)| value == expected)
+            }
+            (Self::HasKey(expected), Value::Object(members)) => {
+                members.iter().any(|(key, _)| key == expected)
             }
+            (&Self::DeeperThan(max_depth), Value::Array(_) | Value::Object(_)) => depth > max_depth,
+            _ => false,
         }
-        Token::ArrayStart => {
-            context.push(current);
-            current = Context::Array;
+    }
+}
+
+/// The sum of the numbers in an array or object, and where it is in the document.
+#[derive(Debug, Clone, PartialEq)]
+struct PathSum {
+    path: String,
+    depth: usize,
+    sum: Number,
+}
+
+/// Sums the numbers in the document, leaving out any array or object that matches one
+/// of the filters.
+fn filtered_sum(value: &Value, filters: &[Filter]) -> Number {
+    PathSummer {
+        filters,
+        sums: None,
+    }
+    .sum(value, 0, &mut "$".to_string())
+}
+
+/// The sum of every array and object that is not filtered out, in document order.
+fn sums_by_path(value: &Value, filters: &[Filter]) -> Vec<PathSum> {
+    let mut summer = PathSummer {
+        filters,
+        sums: Some(Vec::new()),
+    };
+    summer.sum(value, 0, &mut "$".to_string());
+    summer.sums.unwrap_or_default()
+}
+
+struct PathSummer<'a> {
+    filters: &'a [Filter],
+    /// Where to record the sum of each array and object, if wanted.
+    sums: Option<Vec<PathSum>>,
+}
+
+impl PathSummer<'_> {
+    fn sum(&mut self, value: &Value, depth: usize, path: &mut String) -> Number {
+        match value {
+            &Value::Number(number) => return number,
+            Value::Array(_) | Value::Object(_) => {}
+            _ => return Number::Integer(0),
         }
-        Token::ArrayEnd => {
-            current = context.pop().unwrap();
-            if let Context::Object(ref mut octx) = current {
-                octx.expects_value = !octx.expects_value;
+        if self
+            .filters
+            .iter()
+            .any(|filter| filter.matches(value, depth))
+        {
+            return Number::Integer(0);
+        }
+        let slot = self.reserve(path, depth);
+        let len = path.len();
+        let mut sum = Number::Integer(0);
+        if let Value::Array(elements) = value {
+            for (index, element) in elements.iter().enumerate() {
+                let _ = write!(path, "[{index}]");
+                sum = sum + self.sum(element, depth + 1, path);
+                path.truncate(len);
             }
         }
-        Token::ObjectStart => {
-            context.push(current);
-            current = Context::Object(ObjectContext {
-                sum_before: sum,
-                rollback_sum: false,
-                expects_value: false,
-            });
+        if let Value::Object(members) = value {
+            for (key, member) in members {
+                push_key(path, key);
+                sum = sum + self.sum(member, depth + 1, path);
+                path.truncate(len);
+            }
         }
-        Token::ObjectEnd => {
-            if let Context::Object(octx) = current
-                && octx.rollback_sum
-            {
-                sum = octx.sum_before;
+        if let (Some(sums), Some(slot)) = (&mut self.sums, slot) {
+            sums[slot].sum = sum;
+        }
+        sum
+    }
+
+    /// Makes room for the sum at `path`, to be filled in once the children are summed.
+    fn reserve(&mut self, path: &str, depth: usize) -> Option<usize> {
+        let sums = self.sums.as_mut()?;
+        sums.push(PathSum {
+            path: path.to_string(),
+            depth,
+            sum: Number::Integer(0),
+        });
+        Some(sums.len() - 1)
+    }
+}
+
+/// Appends `.key` to the path, or `["key"]` if the key is not a plain identifier.
+fn push_key(path: &mut String, key: &str) {
+    let plain = key
+        .chars()
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && key
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    if plain {
+        path.push('.');
+        path.push_str(key);
+        return;
+    }
+    path.push_str("[\"");
+    for ch in key.chars() {
+        match ch {
+            '"' => path.push_str("\\\""),
+            '\\' => path.push_str("\\\\"),
+            ch if ch.is_control() => {
+                let _ = write!(path, "\\u{:04x}", u32::from(ch));
+            }
+            ch => path.push(ch),
+        }
+    }
+    path.push_str("\"]");
+}
+
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+}
+
+/// How much of the document to read at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Sums the numbers in the document read from `reader`, a chunk at a time.
+///
+/// Only the arrays and objects that are still open are kept in memory, so documents of any
+/// size can be summed. With `skip_red`, objects with a property whose value is `"red"` are
+/// left out, along with everything inside them, as in part 2.
+///
+/// # Errors
+///
+/// Returns an error if reading fails, or if the document is not valid JSON.
+pub fn stream_sum(mut reader: impl Read, skip_red: bool) -> Result<Number, StreamError> {
+    let mut summer = StreamSummer::new(skip_red);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut carried = 0;
+    loop {
+        let read = match reader.read(&mut buffer[carried..]) {
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
+        };
+        let filled = carried + read;
+        carried = 0;
+        let mut offset = 0;
+        for chunk in buffer[..filled].utf8_chunks() {
+            summer.feed(chunk.valid())?;
+            let invalid = chunk.invalid();
+            offset += chunk.valid().len() + invalid.len();
+            if invalid.is_empty() {
+                continue;
             }
-            current = context.pop().unwrap();
-            if let Context::Object(ref mut octx) = current {
-                octx.expects_value = !octx.expects_value;
+            // A character cut off at the end of the chunk may be completed by the next one.
+            if read > 0 && offset == filled {
+                carried = invalid.len();
+            } else {
+                return Err(summer.error(ParseErrorKind::InvalidUtf8).into());
             }
         }
-    });
-    assert_eq!(&context, &[]);
-    sum
+        if read == 0 {
+            return Ok(summer.finish()?);
+        }
+        buffer.copy_within(filled - carried..filled, 0);
+    }
 }
 
+/// What may come next, between tokens.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Context {
-    Array,
-    Object(ObjectContext),
+enum Expect {
+    Value,
+    ValueOrEnd,
+    Key,
+    KeyOrEnd,
+    Colon,
+    CommaOrEnd,
+    Nothing,
 }
 
+/// The token being read, if any.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct ObjectContext {
-    sum_before: i64,
-    rollback_sum: bool,
-    expects_value: bool,
+enum Token {
+    None,
+    String {
+        key: bool,
+        escape: Escape,
+        /// How much of `red` the string has matched so far, if all of it matched.
+        red: Option<usize>,
+    },
+    Number(NumberState),
+    /// The rest of `true`, `false` or `null`.
+    Literal(&'static str),
 }
 
-#[derive(Debug, Clone)]
-enum State {
-    Initial,
-    Integer(i64),
-    Negative(i64),
-    String,
+/// Where in an escape sequence a string is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Backslash,
+    /// Reading the hex digits of `\uXXXX`, after the high surrogate of a pair if any.
+    Unicode {
+        code: u32,
+        digits: u32,
+        high: Option<u32>,
+    },
+    /// After a high surrogate, expecting the `\` of its low surrogate.
+    LowBackslash(u32),
+    /// After a high surrogate, expecting the `u` of its low surrogate.
+    LowU(u32),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[allow(unused)]
-enum Token<'a> {
-    Integer(i64),
-    String(&'a str),
-    ArrayStart,
-    ArrayEnd,
-    ObjectStart,
-    ObjectEnd,
-}
-
-fn lex<F>(input: &[u8], mut callback: F)
-where
-    F: FnMut(Token<'_>),
-{
-    let mut stringvalues = HashMap::<Vec<u8>, String>::new();
-    let mut state = State::Initial;
-    let mut str_val = Vec::new();
-    for &ch in input {
-        let mut again = true;
-        while again {
-            again = false;
-            state = match (state, ch) {
-                (State::Initial, b'-') => State::Negative(0),
-                (State::Initial, b'0'..=b'9') => State::Integer(i64::from(ch - b'0')),
-                (State::Initial, b'[') => {
-                    callback(Token::ArrayStart);
-                    State::Initial
+/// Where in the number grammar a number is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberState {
+    Minus,
+    Zero,
+    Integer,
+    Point,
+    Fraction,
+    Exponent,
+    ExponentSign,
+    ExponentDigits,
+}
+
+impl NumberState {
+    /// The state after `ch`, or `None` if the number ended before it.
+    const fn next(self, ch: char) -> Result<Option<Self>, ParseErrorKind> {
+        Ok(Some(match (self, ch) {
+            (Self::Minus, '0') => Self::Zero,
+            (Self::Minus, '1'..='9') | (Self::Integer, '0'..='9') => Self::Integer,
+            (Self::Zero | Self::Integer, '.') => Self::Point,
+            (Self::Point | Self::Fraction, '0'..='9') => Self::Fraction,
+            (Self::Zero | Self::Integer | Self::Fraction, 'e' | 'E') => Self::Exponent,
+            (Self::Exponent, '+' | '-') => Self::ExponentSign,
+            (Self::Exponent | Self::ExponentSign | Self::ExponentDigits, '0'..='9') => {
+                Self::ExponentDigits
+            }
+            (Self::Zero | Self::Integer | Self::Fraction | Self::ExponentDigits, _) => {
+                return Ok(None);
+            }
+            _ => return Err(ParseErrorKind::InvalidNumber),
+        }))
+    }
+
+    const fn is_complete(self) -> bool {
+        matches!(
+            self,
+            Self::Zero | Self::Integer | Self::Fraction | Self::ExponentDigits
+        )
+    }
+}
+
+/// An array or object that is still open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Frame {
+    object: bool,
+    sum: Number,
+    /// Whether a property of the object has the value `"red"`.
+    red: bool,
+}
+
+/// A state machine that sums a document fed to it a piece at a time.
+struct StreamSummer {
+    skip_red: bool,
+    frames: Vec<Frame>,
+    total: Number,
+    expect: Expect,
+    token: Token,
+    /// The text of the number being read.
+    number: String,
+    line: usize,
+    column: usize,
+}
+
+impl StreamSummer {
+    const fn new(skip_red: bool) -> Self {
+        Self {
+            skip_red,
+            frames: Vec::new(),
+            total: Number::Integer(0),
+            expect: Expect::Value,
+            token: Token::None,
+            number: String::new(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// An error at the character being read.
+    const fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            kind,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn feed(&mut self, text: &str) -> Result<(), ParseError> {
+        for ch in text.chars() {
+            self.step(ch).map_err(|kind| self.error(kind))?;
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += ch.len_utf8();
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the document, returning its sum.
+    fn finish(&mut self) -> Result<Number, ParseError> {
+        match self.token {
+            Token::None => {}
+            Token::Number(state) if state.is_complete() => {
+                self.end_number(state).map_err(|kind| self.error(kind))?;
+            }
+            Token::Number(_) => return Err(self.error(ParseErrorKind::InvalidNumber)),
+            Token::String { .. } | Token::Literal(_) => {
+                return Err(self.error(ParseErrorKind::UnexpectedEnd));
+            }
+        }
+        if self.expect == Expect::Nothing {
+            Ok(self.total)
+        } else {
+            Err(self.error(ParseErrorKind::UnexpectedEnd))
+        }
+    }
+
+    fn step(&mut self, ch: char) -> Result<(), ParseErrorKind> {
+        match self.token {
+            Token::None => self.between_tokens(ch),
+            Token::String { key, escape, red } => self.string(ch, key, escape, red),
+            Token::Number(state) => {
+                if let Some(next) = state.next(ch)? {
+                    self.number.push(ch);
+                    self.token = Token::Number(next);
+                    Ok(())
+                } else {
+                    self.end_number(state)?;
+                    self.between_tokens(ch)
                 }
-                (State::Initial, b']') => {
-                    callback(Token::ArrayEnd);
-                    State::Initial
+            }
+            Token::Literal(rest) => {
+                let rest = rest
+                    .strip_prefix(ch)
+                    .ok_or(ParseErrorKind::UnexpectedCharacter(ch))?;
+                if rest.is_empty() {
+                    self.token = Token::None;
+                    self.end_value(Number::Integer(0));
+                } else {
+                    self.token = Token::Literal(rest);
                 }
-                (State::Initial, b'{') => {
-                    callback(Token::ObjectStart);
-                    State::Initial
+                Ok(())
+            }
+        }
+    }
+
+    fn between_tokens(&mut self, ch: char) -> Result<(), ParseErrorKind> {
+        let object = self.frames.last().is_some_and(|frame| frame.object);
+        match (self.expect, ch) {
+            (_, ' ' | '\t' | '\n' | '\r') => {}
+            (Expect::Value | Expect::ValueOrEnd, '[' | '{') => {
+                if self.frames.len() >= MAX_DEPTH {
+                    return Err(ParseErrorKind::TooDeep);
                 }
-                (State::Initial, b'}') => {
-                    callback(Token::ObjectEnd);
-                    State::Initial
+                self.frames.push(Frame {
+                    object: ch == '{',
+                    sum: Number::Integer(0),
+                    red: false,
+                });
+                self.expect = if ch == '{' {
+                    Expect::KeyOrEnd
+                } else {
+                    Expect::ValueOrEnd
+                };
+            }
+            (Expect::Value | Expect::ValueOrEnd, '"') => self.start_string(false),
+            (Expect::Value | Expect::ValueOrEnd, '-' | '0'..='9') => {
+                self.number.clear();
+                self.number.push(ch);
+                let state = match ch {
+                    '-' => NumberState::Minus,
+                    '0' => NumberState::Zero,
+                    _ => NumberState::Integer,
+                };
+                self.token = Token::Number(state);
+            }
+            (Expect::Value | Expect::ValueOrEnd, 't') => self.token = Token::Literal("rue"),
+            (Expect::Value | Expect::ValueOrEnd, 'f') => self.token = Token::Literal("alse"),
+            (Expect::Value | Expect::ValueOrEnd, 'n') => self.token = Token::Literal("ull"),
+            (Expect::Key | Expect::KeyOrEnd, '"') => self.start_string(true),
+            (Expect::Colon, ':') => self.expect = Expect::Value,
+            (Expect::CommaOrEnd, ',') => {
+                self.expect = if object { Expect::Key } else { Expect::Value };
+            }
+            (Expect::CommaOrEnd | Expect::ValueOrEnd, ']') if !object => self.end_container(),
+            (Expect::CommaOrEnd | Expect::KeyOrEnd, '}') if object => self.end_container(),
+            _ => return Err(ParseErrorKind::UnexpectedCharacter(ch)),
+        }
+        Ok(())
+    }
+
+    const fn start_string(&mut self, key: bool) {
+        self.token = Token::String {
+            key,
+            escape: Escape::None,
+            red: Some(0),
+        };
+    }
+
+    fn string(
+        &mut self,
+        ch: char,
+        key: bool,
+        escape: Escape,
+        red: Option<usize>,
+    ) -> Result<(), ParseErrorKind> {
+        let (escape, decoded) = match (escape, ch) {
+            (Escape::None, '"') => {
+                self.end_string(key, red == Some(3));
+                return Ok(());
+            }
+            (Escape::None, '\\') => (Escape::Backslash, None),
+            (Escape::None, ..'\u{20}') => return Err(ParseErrorKind::ControlCharacter),
+            (Escape::None, ch) => (Escape::None, Some(ch)),
+            (Escape::Backslash, 'u') => (
+                Escape::Unicode {
+                    code: 0,
+                    digits: 0,
+                    high: None,
+                },
+                None,
+            ),
+            (Escape::Backslash, ch) => {
+                let decoded = match ch {
+                    '"' | '\\' | '/' => ch,
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    _ => return Err(ParseErrorKind::InvalidEscape),
+                };
+                (Escape::None, Some(decoded))
+            }
+            (Escape::Unicode { code, digits, high }, ch) => {
+                let digit = ch.to_digit(16).ok_or(ParseErrorKind::InvalidEscape)?;
+                let code = code * 16 + digit;
+                match (digits + 1, high) {
+                    (4, None) if (0xD800..0xDC00).contains(&code) => {
+                        (Escape::LowBackslash(code), None)
+                    }
+                    (4, None) => (Escape::None, Some(unicode_char(code)?)),
+                    (4, Some(high)) if (0xDC00..0xE000).contains(&code) => (
+                        Escape::None,
+                        Some(unicode_char(
+                            0x10000 + ((high - 0xD800) << 10) + (code - 0xDC00),
+                        )?),
+                    ),
+                    (4, Some(_)) => return Err(ParseErrorKind::InvalidEscape),
+                    (digits, high) => (Escape::Unicode { code, digits, high }, None),
                 }
-                (State::Initial, b'"') => State::String,
-                (State::Initial, _) => State::Initial,
-                (State::Integer(val), b'0'..=b'9') => {
-                    State::Integer(val * 10 + i64::from(ch - b'0'))
+            }
+            (Escape::LowBackslash(high), '\\') => (Escape::LowU(high), None),
+            (Escape::LowU(high), 'u') => (
+                Escape::Unicode {
+                    code: 0,
+                    digits: 0,
+                    high: Some(high),
+                },
+                None,
+            ),
+            (Escape::LowBackslash(_) | Escape::LowU(_), _) => {
+                return Err(ParseErrorKind::InvalidEscape);
+            }
+        };
+        let red = red
+            .filter(|&matched| decoded.is_none_or(|decoded| "red"[matched..].starts_with(decoded)));
+        self.token = Token::String {
+            key,
+            escape,
+            red: red.map(|matched| matched + decoded.map_or(0, char::len_utf8)),
+        };
+        Ok(())
+    }
+
+    fn end_string(&mut self, key: bool, red: bool) {
+        self.token = Token::None;
+        if key {
+            self.expect = Expect::Colon;
+            return;
+        }
+        if red && let Some(frame) = self.frames.last_mut() {
+            frame.red |= frame.object;
+        }
+        self.end_value(Number::Integer(0));
+    }
+
+    fn end_number(&mut self, state: NumberState) -> Result<(), ParseErrorKind> {
+        self.token = Token::None;
+        let integer = matches!(state, NumberState::Zero | NumberState::Integer);
+        let number = number_from_text(&self.number, integer)?;
+        self.end_value(number);
+        Ok(())
+    }
+
+    fn end_container(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        if frame.red && self.skip_red {
+            self.end_value(Number::Integer(0));
+        } else {
+            self.end_value(frame.sum);
+        }
+    }
+
+    /// Adds a value that has been read in full to the array or object it is in.
+    fn end_value(&mut self, number: Number) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.sum = frame.sum + number;
+            self.expect = Expect::CommaOrEnd;
+        } else {
+            self.total = self.total + number;
+            self.expect = Expect::Nothing;
+        }
+    }
+}
+
+fn unicode_char(code: u32) -> Result<char, ParseErrorKind> {
+    char::from_u32(code).ok_or(ParseErrorKind::InvalidEscape)
+}
+
+/// Converts the text of a number, keeping it exact if it is an `integer` that fits.
+fn number_from_text(text: &str, integer: bool) -> Result<Number, ParseErrorKind> {
+    if integer && let Ok(number) = text.parse() {
+        return Ok(Number::Integer(number));
+    }
+    text.parse()
+        .map(Number::Float)
+        .map_err(|_| ParseErrorKind::InvalidNumber)
+}
+
+/// A recursive descent parser. On error, `position` is left at the offending byte.
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    #[allow(clippy::naive_bytecount)]
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        let position = self.position.min(self.input.len());
+        let before = &self.input[..position];
+        let line_start = before
+            .iter()
+            .rposition(|&ch| ch == b'\n')
+            .map_or(0, |newline| newline + 1);
+        ParseError {
+            kind,
+            line: before.iter().filter(|&&ch| ch == b'\n').count() + 1,
+            column: position - line_start + 1,
+        }
+    }
+
+    fn document(&mut self) -> Result<Value, ParseErrorKind> {
+        let value = self.value(0)?;
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(value),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn unexpected(&self) -> ParseErrorKind {
+        let rest = &self.input[self.position.min(self.input.len())..];
+        let len = rest.len().min(4);
+        let ch = (1..=len)
+            .find_map(|len| std::str::from_utf8(&rest[..len]).ok())
+            .and_then(|text| text.chars().next());
+        match (rest.first(), ch) {
+            (None, _) => ParseErrorKind::UnexpectedEnd,
+            (Some(_), Some(ch)) => ParseErrorKind::UnexpectedCharacter(ch),
+            (Some(_), None) => ParseErrorKind::InvalidUtf8,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), ParseErrorKind> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, ParseErrorKind> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(depth + 1),
+            Some(b'[') => self.array(depth + 1),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number().map(Value::Number),
+            Some(b't') => self.literal(b"true", Value::Bool(true)),
+            Some(b'f') => self.literal(b"false", Value::Bool(false)),
+            Some(b'n') => self.literal(b"null", Value::Null),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn literal(&mut self, text: &[u8], value: Value) -> Result<Value, ParseErrorKind> {
+        for &expected in text {
+            if self.peek() != Some(expected) {
+                return Err(self.unexpected());
+            }
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, ParseErrorKind> {
+        if depth > MAX_DEPTH {
+            return Err(ParseErrorKind::TooDeep);
+        }
+        self.position += 1;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(elements));
+        }
+        loop {
+            elements.push(self.value(depth)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(elements));
                 }
-                (State::Negative(val), b'0'..=b'9') => {
-                    State::Negative(val * 10 - i64::from(ch - b'0'))
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, ParseErrorKind> {
+        if depth > MAX_DEPTH {
+            return Err(ParseErrorKind::TooDeep);
+        }
+        self.position += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected());
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value(depth)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(members));
                 }
-                (State::Integer(val) | State::Negative(val), _) => {
-                    callback(Token::Integer(val));
-                    again = true;
-                    State::Initial
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseErrorKind> {
+        self.position += 1;
+        let mut string = String::new();
+        loop {
+            let run_start = self.position;
+            while let Some(ch) = self.peek()
+                && ch != b'"'
+                && ch != b'\\'
+                && ch >= 0x20
+            {
+                self.position += 1;
+            }
+            let run = &self.input[run_start..self.position];
+            match std::str::from_utf8(run) {
+                Ok(text) => string.push_str(text),
+                Err(error) => {
+                    self.position = run_start + error.valid_up_to();
+                    return Err(ParseErrorKind::InvalidUtf8);
                 }
-                (State::String, b'"') => {
-                    let str_val_copy = str_val.split_off(0);
-                    let entry = stringvalues
-                        .entry(str_val_copy)
-                        .or_insert_with_key(|b| unsafe { String::from_utf8_unchecked(b.clone()) });
-                    callback(Token::String(&*entry));
-                    State::Initial
+            }
+            match self.peek() {
+                None => return Err(ParseErrorKind::UnexpectedEnd),
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(string);
                 }
-                (State::String, _) => {
-                    str_val.push(ch);
-                    State::String
+                Some(b'\\') => {
+                    self.position += 1;
+                    string.push(self.escape()?);
                 }
+                Some(_) => return Err(ParseErrorKind::ControlCharacter),
+            }
+        }
+    }
+
+    /// Parses the escape sequence after a backslash, leaving `position` after it.
+    fn escape(&mut self) -> Result<char, ParseErrorKind> {
+        let ch = match self.peek() {
+            None => return Err(ParseErrorKind::UnexpectedEnd),
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => return self.unicode_escape(),
+            Some(_) => return Err(ParseErrorKind::InvalidEscape),
+        };
+        self.position += 1;
+        Ok(ch)
+    }
+
+    /// Parses `uXXXX`, or a surrogate pair `uXXXX\uXXXX`.
+    fn unicode_escape(&mut self) -> Result<char, ParseErrorKind> {
+        let high = self.hex_code()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or(ParseErrorKind::InvalidEscape);
+        }
+        if !self.input[self.position..].starts_with(b"\\u") {
+            return Err(ParseErrorKind::InvalidEscape);
+        }
+        self.position += 1;
+        let low = self.hex_code()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(ParseErrorKind::InvalidEscape);
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or(ParseErrorKind::InvalidEscape)
+    }
+
+    /// Parses `uXXXX`, starting at the `u`.
+    fn hex_code(&mut self) -> Result<u32, ParseErrorKind> {
+        let digits = self
+            .input
+            .get(self.position + 1..self.position + 5)
+            .ok_or(ParseErrorKind::UnexpectedEnd)?;
+        let code = std::str::from_utf8(digits)
+            .ok()
+            .filter(|digits| digits.bytes().all(|ch| ch.is_ascii_hexdigit()))
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or(ParseErrorKind::InvalidEscape)?;
+        self.position += 5;
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Number, ParseErrorKind> {
+        let start = self.position;
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(ParseErrorKind::InvalidNumber),
+        }
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            integer = false;
+            self.position += 1;
+            self.required_digits()?;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            integer = false;
+            self.position += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.position += 1;
             }
+            self.required_digits()?;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.position])
+            .map_err(|_| ParseErrorKind::InvalidNumber)?;
+        number_from_text(text, integer)
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), ParseErrorKind> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return Err(ParseErrorKind::InvalidNumber);
         }
+        self.digits();
+        Ok(())
     }
 }
 
@@ -162,34 +1008,246 @@ mod tests {
     use super::*;
     use test_case::test_case;
 
-    #[test_case(br"[1,2,3]", &[Token::ArrayStart, Token::Integer(1), Token::Integer(2), Token::Integer(3), Token::ArrayEnd])]
-    #[test_case(br"[-123]", &[Token::ArrayStart, Token::Integer(-123), Token::ArrayEnd])]
-    #[test_case(br#"[1,{"c":"red","b":2},3]"#, &[Token::ArrayStart, Token::Integer(1), Token::ObjectStart, Token::String("c"), Token::String("red"), Token::String("b"),Token::Integer(2), Token::ObjectEnd, Token::Integer(3), Token::ArrayEnd])]
-    fn test_lex(input: &[u8], expects: &[Token]) {
-        let mut it = expects.iter();
-        lex(input, |tok| {
-            assert_eq!(&tok, it.next().unwrap());
-        });
-        assert!(it.next().is_none());
-    }
-
-    #[test_case(br"[1,2,3]" => 6)]
-    #[test_case(br#"{"a":2,"b":4}"# => 6)]
-    #[test_case(br"[[[3]]]" => 3)]
-    #[test_case(br#"{"a":{"b":4},"c":-1}"# => 3)]
-    #[test_case(br#"{"a":[-1,1]}"# => 0)]
-    #[test_case(br#"[-1,{"a":1}]"# => 0)]
-    #[test_case(br"[]" => 0; "empty arr expects 0")]
-    #[test_case(br"{}" => 0; "empty obj expects 0")]
-    fn test_part_1(input: &[u8]) -> i64 {
-        part_1(input)
-    }
-
-    #[test_case(br"[1,2,3]" => 6)]
-    #[test_case(br#"[1,{"c":"red","b":2},3]"# => 4)]
-    #[test_case(br#"{"d":"red","e":[1,2,3,4],"f":5}"# => 0)]
-    #[test_case(br#"[1,"red",5]"# => 6)]
-    fn test_part_2(input: &[u8]) -> i64 {
-        part_2(input)
+    #[test]
+    fn test_parse() {
+        let input = r#" {"a": [1, -2.5e1, true, false, null], "b\"é\ud83d\ude00😀": {}} "#;
+        let expected = Value::Object(vec![
+            (
+                "a".to_string(),
+                Value::Array(vec![
+                    Value::Number(Number::Integer(1)),
+                    Value::Number(Number::Float(-25.0)),
+                    Value::Bool(true),
+                    Value::Bool(false),
+                    Value::Null,
+                ]),
+            ),
+            ("b\"é😀😀".to_string(), Value::Object(Vec::new())),
+        ]);
+        assert_eq!(parse(input.as_bytes()), Ok(expected));
+    }
+
+    #[test_case(b"" => (ParseErrorKind::UnexpectedEnd, 1, 1))]
+    #[test_case(b"[1,2" => (ParseErrorKind::UnexpectedEnd, 1, 5))]
+    #[test_case(b"[1,]" => (ParseErrorKind::UnexpectedCharacter(']'), 1, 4))]
+    #[test_case(b"{\n  \"a\" 1\n}" => (ParseErrorKind::UnexpectedCharacter('1'), 2, 7))]
+    #[test_case(b"{1: 2}" => (ParseErrorKind::UnexpectedCharacter('1'), 1, 2))]
+    #[test_case(b"[1] [2]" => (ParseErrorKind::UnexpectedCharacter('['), 1, 5))]
+    #[test_case(br#"["\x"]"# => (ParseErrorKind::InvalidEscape, 1, 4))]
+    #[test_case(br#"["\ud800"]"# => (ParseErrorKind::InvalidEscape, 1, 9))]
+    #[test_case(b"[\"a\tb\"]" => (ParseErrorKind::ControlCharacter, 1, 4))]
+    #[test_case(b"[\"a\xffb\"]" => (ParseErrorKind::InvalidUtf8, 1, 4))]
+    #[test_case(b"[01]" => (ParseErrorKind::UnexpectedCharacter('1'), 1, 3))]
+    #[test_case(b"[1.]" => (ParseErrorKind::InvalidNumber, 1, 4))]
+    #[test_case(b"[-]" => (ParseErrorKind::InvalidNumber, 1, 3))]
+    #[test_case(b"[tru]" => (ParseErrorKind::UnexpectedCharacter(']'), 1, 5))]
+    fn test_parse_error(input: &[u8]) -> (ParseErrorKind, usize, usize) {
+        let error = parse(input).unwrap_err();
+        (error.kind, error.line, error.column)
+    }
+
+    #[test]
+    fn test_too_deep() {
+        let input = [b"[".repeat(MAX_DEPTH), b"]".repeat(MAX_DEPTH)].concat();
+        assert!(parse(&input).is_ok());
+        let input = [b"[".repeat(MAX_DEPTH + 1), b"]".repeat(MAX_DEPTH + 1)].concat();
+        assert_eq!(parse(&input).unwrap_err().kind, ParseErrorKind::TooDeep);
+    }
+
+    #[test_case(br"[1,2,3]" => Number::Integer(6))]
+    #[test_case(br#"{"a":2,"b":4}"# => Number::Integer(6))]
+    #[test_case(br"[[[3]]]" => Number::Integer(3))]
+    #[test_case(br#"{"a":{"b":4},"c":-1}"# => Number::Integer(3))]
+    #[test_case(br#"{"a":[-1,1]}"# => Number::Integer(0))]
+    #[test_case(br#"[-1,{"a":1}]"# => Number::Integer(0))]
+    #[test_case(br"[]" => Number::Integer(0); "empty arr expects 0")]
+    #[test_case(br"{}" => Number::Integer(0); "empty obj expects 0")]
+    #[test_case(br#"["\"12\"", 3]"# => Number::Integer(3); "escaped quotes")]
+    #[test_case(br"[1.5, 2]" => Number::Float(3.5); "float")]
+    fn test_part_1(input: &[u8]) -> Number {
+        part_1(&parse(input).unwrap())
+    }
+
+    #[test_case(r#"value="red""# => Ok(Filter::PropertyValue(Value::String("red".to_string()))))]
+    #[test_case("value=[1, 2]" => Ok(Filter::PropertyValue(Value::Array(vec![Value::Number(Number::Integer(1)), Value::Number(Number::Integer(2))]))))]
+    #[test_case("key=a b" => Ok(Filter::HasKey("a b".to_string())))]
+    #[test_case("depth>2" => Ok(Filter::DeeperThan(2)))]
+    #[test_case("depth<2" => Err(FilterError::UnknownFilter("depth<2".to_string())))]
+    fn test_parse_filter(query: &str) -> Result<Filter, FilterError> {
+        query.parse()
+    }
+
+    #[test]
+    fn test_parse_filter_errors() {
+        assert!(matches!(
+            "value=red".parse::<Filter>(),
+            Err(FilterError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            "depth>-1".parse::<Filter>(),
+            Err(FilterError::InvalidDepth(_))
+        ));
+    }
+
+    const NESTED: &[u8] =
+        br#"{"a": [1, {"b": 2, "c": "red"}, [3, {"d": 4}]], "e f": {"g": 5}, "h": 6}"#;
+
+    #[test_case(&[] => Number::Integer(21); "no filter")]
+    #[test_case(&[r#"value="red""#] => Number::Integer(19); "red")]
+    #[test_case(&["key=g"] => Number::Integer(16); "key")]
+    #[test_case(&["depth>1"] => Number::Integer(12); "depth")]
+    #[test_case(&["depth>0"] => Number::Integer(6); "top level only")]
+    #[test_case(&["key=h"] => Number::Integer(0); "whole document")]
+    #[test_case(&["key=g", "key=d"] => Number::Integer(12); "several filters")]
+    fn test_filtered_sum(filters: &[&str]) -> Number {
+        let filters: Vec<Filter> = filters
+            .iter()
+            .map(|filter| filter.parse().unwrap())
+            .collect();
+        filtered_sum(&parse(NESTED).unwrap(), &filters)
+    }
+
+    #[test]
+    fn test_sums_by_path() {
+        let filters = [r#"value="red""#.parse().unwrap()];
+        let sums: Vec<(String, usize, Number)> = sums_by_path(&parse(NESTED).unwrap(), &filters)
+            .into_iter()
+            .map(|PathSum { path, depth, sum }| (path, depth, sum))
+            .collect();
+        assert_eq!(
+            sums,
+            [
+                ("$".to_string(), 0, Number::Integer(19)),
+                ("$.a".to_string(), 1, Number::Integer(8)),
+                ("$.a[2]".to_string(), 2, Number::Integer(7)),
+                ("$.a[2][1]".to_string(), 3, Number::Integer(4)),
+                (r#"$["e f"]"#.to_string(), 1, Number::Integer(5)),
+            ]
+        );
+    }
+
+    #[test_case("plain_key9" => "$.plain_key9")]
+    #[test_case("9lives" => r#"$["9lives"]"#)]
+    #[test_case("" => r#"$[""]"#)]
+    #[test_case("say \"hi\"\\\n" => r#"$["say \"hi\"\\\u000a"]"#)]
+    fn test_push_key(key: &str) -> String {
+        let mut path = "$".to_string();
+        push_key(&mut path, key);
+        path
+    }
+
+    #[test_case(br"[1,2,3]" => Ok(Number::Integer(6)))]
+    #[test_case(br#"[1,{"c":"red","b":2},3]"# => Ok(Number::Integer(4)))]
+    #[test_case(br#"{"d":"red","e":[1,2,3,4],"f":5}"# => Ok(Number::Integer(0)))]
+    #[test_case(br#"[1,"red",5]"# => Ok(Number::Integer(6)))]
+    #[test_case(br#"[1,{"red":2},3]"# => Ok(Number::Integer(6)); "red key")]
+    fn test_part_2(input: &[u8]) -> Result<Number, FilterError> {
+        part_2(&parse(input).unwrap())
+    }
+
+    /// Hands out the input a byte at a time, to split every token across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test_case(br"[1,2,3]")]
+    #[test_case(br#"[1,{"c":"red","b":2},3]"#)]
+    #[test_case(br#"{"d":"red","e":[1,2,3,4],"f":5}"#)]
+    #[test_case(br#"[1,"red",5]"#)]
+    #[test_case(br#"[1,{"red":2},3]"#; "red key")]
+    #[test_case(br#"[1,{"a":"\u0072ed","b":2}]"#; "escaped red")]
+    #[test_case(br#"[1,{"a":"reds","b":2},{"a":"re","b":3}]"#; "almost red")]
+    #[test_case(br"[1.5, -2e1, 0.25E+1, 0]"; "floats")]
+    #[test_case(br" 42 "; "number document")]
+    #[test_case(br#""red""#; "string document")]
+    #[test_case(r#"{"é😀\ud83d\ude00": [true, false, null, 7]}"#.as_bytes(); "unicode")]
+    #[test_case(NESTED; "nested")]
+    fn test_stream_sum(input: &[u8]) {
+        let document = parse(input).unwrap();
+        for skip_red in [false, true] {
+            let expected = if skip_red {
+                part_2(&document).unwrap()
+            } else {
+                part_1(&document)
+            };
+            assert_eq!(stream_sum(input, skip_red).unwrap(), expected);
+            assert_eq!(stream_sum(Trickle(input), skip_red).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_stream_sum_large() {
+        let element = br#"{"a":[1,2,{"b":"red","c":[3]}],"d":"blue","e":-4},"#;
+        let count = 3 * CHUNK_SIZE / element.len();
+        let input = [b"[".as_slice(), &element.repeat(count), b"5]"].concat();
+        assert_eq!(
+            stream_sum(input.as_slice(), false).unwrap(),
+            Number::Integer(2 * i64::try_from(count).unwrap() + 5)
+        );
+        assert_eq!(
+            stream_sum(input.as_slice(), true).unwrap(),
+            Number::Integer(-i64::try_from(count).unwrap() + 5)
+        );
+    }
+
+    #[test]
+    fn test_stream_sum_error() {
+        let inputs: [&[u8]; 20] = [
+            b"",
+            b"[1,2",
+            b"[1,]",
+            b"{\n  \"a\" 1\n}",
+            b"{1: 2}",
+            b"[1] [2]",
+            br#"["\x"]"#,
+            br#"["\ud800"]"#,
+            b"[\"a\tb\"]",
+            b"[\"a\xffb\"]",
+            b"[\"a\xe2\x82\"]",
+            b"[\"a\xe2\x82",
+            b"[01]",
+            b"[1.]",
+            b"[1e]",
+            b"[-]",
+            b"[1.5",
+            b"[tru]",
+            b"[\"abc",
+            b"[1]\xff",
+        ];
+        for input in inputs {
+            let expected = parse(input).unwrap_err();
+            for result in [stream_sum(input, false), stream_sum(Trickle(input), false)] {
+                match result {
+                    Err(StreamError::Parse(error)) => assert_eq!(error, expected),
+                    other => panic!("expected {expected:?}, got {other:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_sum_too_deep() {
+        let input = [b"[".repeat(MAX_DEPTH), b"]".repeat(MAX_DEPTH)].concat();
+        assert_eq!(
+            stream_sum(input.as_slice(), false).unwrap(),
+            Number::Integer(0)
+        );
+        let input = [b"[".repeat(MAX_DEPTH + 1), b"]".repeat(MAX_DEPTH + 1)].concat();
+        let Err(StreamError::Parse(error)) = stream_sum(input.as_slice(), false) else {
+            panic!("expected an error");
+        };
+        assert_eq!(error, parse(&input).unwrap_err());
     }
 }
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
 
 use thiserror::Error;
 
@@ -10,23 +11,118 @@ enum ParseError {
     MissingDelimiter,
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+enum DerivationError {
+    #[error("The molecule cannot be made from e with these rules")]
+    Underivable,
+    #[error(transparent)]
+    InvalidStep(#[from] ReplayError),
+    #[error("Replaying the derivation does not make the molecule")]
+    WrongMolecule,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum ReplayError {
+    #[error("Step {step} uses rule {rule}, which does not exist")]
+    UnknownRule { step: usize, rule: usize },
+    #[error("Step {step} replaces position {position}, past the end of the molecule")]
+    OutOfRange { step: usize, position: usize },
+    #[error("Step {step} applies a rule for {expected} to {found}")]
+    WrongAtom {
+        step: usize,
+        expected: String,
+        found: String,
+    },
+}
+
+/// An element, interned in an [`AtomTable`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum Atom {
-    Empty,
-    Single(u8),
-    Double(u8, u8),
+struct Atom(u32);
+
+impl Atom {
+    /// The electron every molecule is made from.
+    const EMPTY: Self = Self(0);
 }
 
-impl TryFrom<&[u8]> for Atom {
-    type Error = ParseError;
+/// The names of the atoms in a schema.
+#[derive(Debug, Clone)]
+struct AtomTable {
+    names: Vec<String>,
+    atoms: HashMap<String, Atom>,
+}
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        Ok(match value {
-            [b'e'] => Self::Empty,
-            [a @ b'A'..=b'Z'] => Self::Single(*a),
-            [a @ b'A'..=b'Z', b @ b'a'..=b'z'] => Self::Double(*a, *b),
-            _ => Err(ParseError::InvalidAtom)?,
-        })
+impl Default for AtomTable {
+    fn default() -> Self {
+        let mut table = Self {
+            names: Vec::new(),
+            atoms: HashMap::new(),
+        };
+        table.intern("e");
+        table
+    }
+}
+
+impl AtomTable {
+    fn intern(&mut self, name: &str) -> Atom {
+        if let Some(&atom) = self.atoms.get(name) {
+            return atom;
+        }
+        let atom = Atom(u32::try_from(self.names.len()).expect("too many atoms"));
+        self.names.push(name.to_string());
+        self.atoms.insert(name.to_string(), atom);
+        atom
+    }
+
+    fn name(&self, atom: Atom) -> &str {
+        &self.names[atom.0 as usize]
+    }
+
+    const fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Splits text into atoms. An atom is either `e`, or an uppercase letter followed
+    /// by any number of lowercase letters and then digits, like `H`, `Uue` or `O2`.
+    fn tokenize(&mut self, text: &[u8]) -> Result<Vec<Atom>, ParseError> {
+        let mut atoms = Vec::new();
+        let mut rest = text;
+        while let Some(&first) = rest.first() {
+            let len = match first {
+                b'e' => 1,
+                b'A'..=b'Z' => {
+                    let letters = rest[1..]
+                        .iter()
+                        .take_while(|ch| ch.is_ascii_lowercase())
+                        .count();
+                    let digits = rest[1 + letters..]
+                        .iter()
+                        .take_while(|ch| ch.is_ascii_digit())
+                        .count();
+                    1 + letters + digits
+                }
+                _ => return Err(ParseError::InvalidAtom),
+            };
+            let (name, tail) = rest.split_at(len);
+            if first == b'e' && tail.first().is_some_and(u8::is_ascii_lowercase) {
+                return Err(ParseError::InvalidAtom);
+            }
+            let name = std::str::from_utf8(name).map_err(|_| ParseError::InvalidAtom)?;
+            atoms.push(self.intern(name));
+            rest = tail;
+        }
+        Ok(atoms)
+    }
+
+    fn molecule_to_string(&self, molecule: &[Atom]) -> String {
+        molecule.iter().map(|&atom| self.name(atom)).collect()
+    }
+
+    fn rule_to_string(&self, rule: &Rule) -> String {
+        format!(
+            "{} => {}",
+            self.name(rule.source),
+            self.molecule_to_string(&rule.target)
+        )
     }
 }
 
@@ -36,24 +132,22 @@ struct Rule {
     target: Vec<Atom>,
 }
 
-impl TryFrom<&[u8]> for Rule {
-    type Error = ParseError;
-
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let arrow = (0..value.len())
-            .find(|&i| value[i..].starts_with(b" => "))
+impl Rule {
+    fn parse(line: &[u8], atoms: &mut AtomTable) -> Result<Self, ParseError> {
+        let arrow = (0..line.len())
+            .find(|&i| line[i..].starts_with(b" => "))
             .ok_or(ParseError::MissingDelimiter)?;
-        let source: Atom = value[..arrow].try_into()?;
-        let target = value[arrow + 4..]
-            .chunk_by(|_, b| u8::is_ascii_lowercase(b))
-            .map(Atom::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
+        let &[source] = atoms.tokenize(&line[..arrow])?.as_slice() else {
+            return Err(ParseError::InvalidAtom);
+        };
+        let target = atoms.tokenize(&line[arrow + 4..])?;
         Ok(Self { source, target })
     }
 }
 
 #[derive(Debug)]
 struct Schema {
+    atoms: AtomTable,
     rules: Vec<Rule>,
     target: Vec<Atom>,
 }
@@ -63,19 +157,21 @@ impl TryFrom<&[u8]> for Schema {
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         let mut lines = value.split(|&ch| ch == b'\n');
+        let mut atoms = AtomTable::default();
         let mut rules = Vec::new();
         for line in &mut lines {
             if line.is_empty() {
                 break;
             }
-            rules.push(line.try_into()?);
+            rules.push(Rule::parse(line, &mut atoms)?);
         }
         let last_line = lines.next().ok_or(ParseError::MissingDelimiter)?;
-        let target = last_line
-            .chunk_by(|_, b| u8::is_ascii_lowercase(b))
-            .map(Atom::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self { rules, target })
+        let target = atoms.tokenize(last_line)?;
+        Ok(Self {
+            atoms,
+            rules,
+            target,
+        })
     }
 }
 
@@ -102,44 +198,790 @@ fn part_1(input: &Schema) -> usize {
     seen.len()
 }
 
-#[aoc(day19, part2)]
-fn part_2(input: &Schema) -> usize {
-    // There are two kinds of rules:
-    // 1) X => XX,
-    // 2) X => X(X) | X(X,X) | X(X,X,X)
-    // To figure out number of rule applications, you just need to count number of open parens or commas.
-    // Except, we need to figure out which atoms act like open paren and comma.
-    let (open, separator) = input
-        .rules
+#[aoc(day19, part1, analytic)]
+fn part_1_analytic(input: &Schema) -> usize {
+    count_single_replacements(&input.rules, &input.target)
+}
+
+/// Counts the distinct molecules made by one replacement in `molecule`, without
+/// building any of them.
+///
+/// Replacing atom `i` with `T`, and atom `j > i` with `U`, gives the same molecule
+/// exactly when `T` followed by atoms `i + 1..=j` equals atoms `i..j` followed by `U`.
+/// A replacement is counted unless an earlier one gives the same molecule.
+fn count_single_replacements(rules: &[Rule], molecule: &[Atom]) -> usize {
+    let max_len = rules
         .iter()
-        .find_map(|r| {
-            if let &[_, op, _, sep, _, ..] = r.target.as_slice() {
-                Some((op, sep))
+        .map(|rule| rule.target.len())
+        .max()
+        .unwrap_or(0);
+    let tables: Vec<_> = (0..=max_len)
+        .map(|len| OverlapTable::new(rules, molecule, len))
+        .collect();
+    // Rules that repeat an earlier rule give the same molecules as it everywhere.
+    let repeated: Vec<bool> = rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| rules[..index].contains(rule))
+        .collect();
+
+    let mut count = 0;
+    for (position, &atom) in molecule.iter().enumerate() {
+        for (rule, &repeated) in rules.iter().zip(&repeated) {
+            if rule.source == atom
+                && !repeated
+                && !tables[rule.target.len()].made_earlier(rules, molecule, position, rule)
+            {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Where in a molecule replacements with targets of one length can give the same
+/// molecule as a replacement further on.
+struct OverlapTable {
+    len: usize,
+    /// `periodic[j]` is the first position from which every atom before `j` equals the
+    /// atom `len - 1` places before it.
+    periodic: Vec<usize>,
+    /// `inserting[i]` is the number of positions before `i` where a rule replaces the
+    /// atom by itself and the atoms following it.
+    inserting: Vec<usize>,
+}
+
+impl OverlapTable {
+    fn new(rules: &[Rule], molecule: &[Atom], len: usize) -> Self {
+        let growth = len.saturating_sub(1);
+        let mut periodic = Vec::with_capacity(molecule.len() + 1);
+        periodic.push(0);
+        for (position, &atom) in molecule.iter().enumerate() {
+            let repeats = position >= growth && molecule[position - growth] == atom;
+            periodic.push(if repeats {
+                periodic[position]
             } else {
-                None
+                position + 1
+            });
+        }
+
+        let mut inserting = Vec::with_capacity(molecule.len() + 1);
+        inserting.push(0);
+        for (position, &atom) in molecule.iter().enumerate() {
+            let inserts = molecule.get(position..position + len).is_some_and(|atoms| {
+                rules
+                    .iter()
+                    .any(|rule| rule.source == atom && rule.target == atoms)
+            });
+            inserting.push(inserting[position] + usize::from(inserts));
+        }
+
+        Self {
+            len,
+            periodic,
+            inserting,
+        }
+    }
+
+    /// Whether a replacement before `position` gives the same molecule as replacing the
+    /// atom at `position` using `rule`.
+    fn made_earlier(
+        &self,
+        rules: &[Rule],
+        molecule: &[Atom],
+        position: usize,
+        rule: &Rule,
+    ) -> bool {
+        let len = self.len;
+        if len == 0 {
+            // Removing any atom in a run of equal atoms gives the same molecule.
+            return position > 0 && molecule[position - 1] == molecule[position];
+        }
+
+        // Earlier replacements that overlap this one's target are compared directly.
+        let close = position.saturating_sub(len - 1)..position;
+        let overlapping = close.into_iter().any(|earlier| {
+            rules.iter().any(|other| {
+                other.source == molecule[earlier]
+                    && other.target.len() == len
+                    && other
+                        .target
+                        .iter()
+                        .chain(&molecule[earlier + 1..=position])
+                        .eq(molecule[earlier..position].iter().chain(&rule.target))
+            })
+        });
+        if overlapping {
+            return true;
+        }
+
+        // Further back, the target has to repeat the atoms before it, the earlier one
+        // has to repeat the atoms after it, and everything between has to be periodic.
+        let Some(last_earlier) = position.checked_sub(len) else {
+            return false;
+        };
+        if molecule[position + 1 - len..=position] != rule.target {
+            return false;
+        }
+        let first_earlier = self.periodic[position].saturating_sub(len);
+        first_earlier <= last_earlier
+            && self.inserting[last_earlier + 1] > self.inserting[first_earlier]
+    }
+}
+
+#[aoc(day19, part1, reachability)]
+fn part_1_reachability(input: &Schema) -> String {
+    let mut output = String::new();
+    for steps in 1..=2 {
+        let counts = count_reachable(&input.rules, &input.target, steps);
+        let _ = write!(
+            output,
+            "\n{steps} steps: {} exactly, {} at most",
+            counts.exactly, counts.at_most
+        );
+    }
+    let from_empty = steps_to_reach(&input.rules, &[Atom::EMPTY], &input.target, 5);
+    let _ = match from_empty {
+        Some(steps) => write!(output, "\nMade from e in {steps} steps"),
+        None => write!(output, "\nNot made from e within 5 steps"),
+    };
+    output
+}
+
+/// The number of distinct molecules that can be made from a molecule in exactly some
+/// number of steps, and in at most that many.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReachCounts {
+    exactly: usize,
+    at_most: usize,
+}
+
+/// Counts the molecules reachable from `start` in `steps` replacements. Molecules are
+/// deduplicated by fingerprint, and those made in the last step are never built.
+fn count_reachable(rules: &[Rule], start: &[Atom], steps: usize) -> ReachCounts {
+    let mut hasher = MoleculeHasher::new(rules);
+    let mut reached = HashSet::from([hasher.fingerprint(start)]);
+    let mut layer = vec![start.to_vec()];
+    let mut exactly = 1;
+    for step in 1..=steps {
+        let last = step == steps;
+        let mut seen = HashSet::new();
+        let mut next = Vec::new();
+        for molecule in &layer {
+            hasher.for_each_successor(molecule, |fingerprint, position, rule| {
+                if seen.insert(fingerprint) {
+                    reached.insert(fingerprint);
+                    if !last {
+                        next.push(replace(molecule, position, rule));
+                    }
+                }
+            });
+        }
+        exactly = seen.len();
+        layer = next;
+    }
+    ReachCounts {
+        exactly,
+        at_most: reached.len(),
+    }
+}
+
+/// The fewest replacements that turn `start` into `goal`, if it takes at most
+/// `max_steps`.
+fn steps_to_reach(
+    rules: &[Rule],
+    start: &[Atom],
+    goal: &[Atom],
+    max_steps: usize,
+) -> Option<usize> {
+    let mut hasher = MoleculeHasher::new(rules);
+    let goal_fingerprint = hasher.fingerprint(goal);
+    let start_fingerprint = hasher.fingerprint(start);
+    if start_fingerprint == goal_fingerprint {
+        return Some(0);
+    }
+    // Without rules that remove atoms, molecules never get shorter, so anything longer
+    // than the goal is a dead end.
+    let max_len = if rules.iter().all(|rule| !rule.target.is_empty()) {
+        goal.len()
+    } else {
+        usize::MAX
+    };
+    let mut reached = HashSet::from([start_fingerprint]);
+    let mut layer = vec![start.to_vec()];
+    for step in 1..=max_steps {
+        let mut next = Vec::new();
+        let mut found = false;
+        for molecule in &layer {
+            hasher.for_each_successor(molecule, |fingerprint, position, rule| {
+                found |= fingerprint == goal_fingerprint;
+                let len = molecule.len() + rule.target.len() - 1;
+                if len <= max_len && reached.insert(fingerprint) {
+                    next.push(replace(molecule, position, rule));
+                }
+            });
+        }
+        if found {
+            return Some(step);
+        }
+        layer = next;
+    }
+    None
+}
+
+fn replace(molecule: &[Atom], position: usize, rule: &Rule) -> Vec<Atom> {
+    let mut replaced = Vec::with_capacity(molecule.len() + rule.target.len());
+    replaced.extend_from_slice(&molecule[..position]);
+    replaced.extend_from_slice(&rule.target);
+    replaced.extend_from_slice(&molecule[position + 1..]);
+    replaced
+}
+
+/// Two polynomial hashes of a molecule, with different bases.
+type Fingerprint = [u64; 2];
+
+/// Fingerprints molecules, including those one replacement away from a molecule,
+/// without building them.
+struct MoleculeHasher<'a> {
+    rules: &'a [Rule],
+    hashes: [PolynomialHash; 2],
+    /// The fingerprint of each rule's target.
+    targets: Vec<Fingerprint>,
+}
+
+impl<'a> MoleculeHasher<'a> {
+    fn new(rules: &'a [Rule]) -> Self {
+        let hashes = [
+            PolynomialHash::new(0x0123_4567_89ab_cdef),
+            PolynomialHash::new(0x0fed_cba9_8765_4321),
+        ];
+        let targets = rules
+            .iter()
+            .map(|rule| hashes.each_ref().map(|hash| hash.of(&rule.target)))
+            .collect();
+        Self {
+            rules,
+            hashes,
+            targets,
+        }
+    }
+
+    fn fingerprint(&self, molecule: &[Atom]) -> Fingerprint {
+        self.hashes.each_ref().map(|hash| hash.of(molecule))
+    }
+
+    /// Calls `visit` with the fingerprint of every molecule made by one replacement in
+    /// `molecule`, along with the position and rule of the replacement.
+    fn for_each_successor(
+        &mut self,
+        molecule: &[Atom],
+        mut visit: impl FnMut(Fingerprint, usize, &'a Rule),
+    ) {
+        let prefixes = self.hashes.each_ref().map(|hash| hash.prefixes(molecule));
+        for (position, &atom) in molecule.iter().enumerate() {
+            let suffix_len = molecule.len() - position - 1;
+            for (rule, &target) in self.rules.iter().zip(&self.targets) {
+                if rule.source != atom {
+                    continue;
+                }
+                let fingerprint = [0, 1].map(|index| {
+                    let hash = &mut self.hashes[index];
+                    let prefixes = &prefixes[index];
+                    let target = target[index];
+                    let suffix = hash.slice(prefixes, position + 1, molecule.len());
+                    let replaced = hash.concat(prefixes[position], target, rule.target.len());
+                    hash.concat(replaced, suffix, suffix_len)
+                });
+                visit(fingerprint, position, rule);
             }
+        }
+    }
+}
+
+const MERSENNE_61: u64 = (1 << 61) - 1;
+
+/// A hash of a molecule as a polynomial in `base`, modulo the prime 2^61 - 1. The hash
+/// of a molecule can be put together from the hashes of its parts.
+struct PolynomialHash {
+    base: u64,
+    /// Powers of the base, extended as longer molecules are seen.
+    powers: Vec<u64>,
+}
+
+impl PolynomialHash {
+    fn new(base: u64) -> Self {
+        Self {
+            base: base % MERSENNE_61,
+            powers: vec![1],
+        }
+    }
+
+    fn power(&mut self, exponent: usize) -> u64 {
+        while self.powers.len() <= exponent {
+            let last = self.powers[self.powers.len() - 1];
+            self.powers.push(mul_mod(last, self.base));
+        }
+        self.powers[exponent]
+    }
+
+    fn push(&self, hash: u64, atom: Atom) -> u64 {
+        add_mod(mul_mod(hash, self.base), u64::from(atom.0) + 1)
+    }
+
+    fn of(&self, molecule: &[Atom]) -> u64 {
+        molecule.iter().fold(0, |hash, &atom| self.push(hash, atom))
+    }
+
+    /// The hashes of every prefix of the molecule, from the empty one to all of it.
+    fn prefixes(&self, molecule: &[Atom]) -> Vec<u64> {
+        let mut prefixes = Vec::with_capacity(molecule.len() + 1);
+        prefixes.push(0);
+        for &atom in molecule {
+            prefixes.push(self.push(prefixes[prefixes.len() - 1], atom));
+        }
+        prefixes
+    }
+
+    /// The hash of the atoms `start..end`, given the hashes of the prefixes.
+    fn slice(&mut self, prefixes: &[u64], start: usize, end: usize) -> u64 {
+        let shifted = mul_mod(prefixes[start], self.power(end - start));
+        add_mod(prefixes[end], MERSENNE_61 - shifted)
+    }
+
+    /// The hash of a molecule hashing to `left`, followed by one of `right_len` atoms
+    /// hashing to `right`.
+    fn concat(&mut self, left: u64, right: u64, right_len: usize) -> u64 {
+        add_mod(mul_mod(left, self.power(right_len)), right)
+    }
+}
+
+const fn add_mod(a: u64, b: u64) -> u64 {
+    let sum = a + b;
+    if sum >= MERSENNE_61 {
+        sum - MERSENNE_61
+    } else {
+        sum
+    }
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    reason = "Both halves are below 2^61"
+)]
+const fn mul_mod(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    let folded = (product as u64 & MERSENNE_61) + (product >> 61) as u64;
+    add_mod(folded & MERSENNE_61, folded >> 61)
+}
+
+#[aoc(day19, part2)]
+fn part_2(input: &Schema) -> Result<u32, DerivationError> {
+    Chart::new(input).steps_from_empty()
+}
+
+#[aoc(day19, part2, derivation)]
+fn part_2_derivation(input: &Schema) -> Result<String, DerivationError> {
+    let derivation = Chart::new(input).derivation()?;
+    let steps = derivation.steps();
+    let molecules = replay(input, &steps)?;
+    if molecules.last().map_or(&[Atom::EMPTY][..], Vec::as_slice) != input.target {
+        return Err(DerivationError::WrongMolecule);
+    }
+    let mut output = format!("\n{}", derivation.tree(&input.atoms));
+    for (number, (step, molecule)) in steps.iter().zip(&molecules).enumerate() {
+        let rule = input.atoms.rule_to_string(&input.rules[step.rule]);
+        let molecule = input.atoms.molecule_to_string(molecule);
+        let _ = write!(
+            output,
+            "\n{}: {rule} at {}: {molecule}",
+            number + 1,
+            step.position
+        );
+    }
+    Ok(output)
+}
+
+/// How a span of the molecule is made from one atom: either the atom is left as it is,
+/// or a rule is applied to it, and each atom it produces is derived in turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Derivation {
+    atom: Atom,
+    /// An index into the schema's rules.
+    rule: Option<usize>,
+    children: Vec<Self>,
+}
+
+/// An application of the schema's rule `rule` to the atom at `position`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Step {
+    rule: usize,
+    position: usize,
+}
+
+impl Derivation {
+    /// The rule applications of the leftmost derivation, in the order they are applied.
+    fn steps(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        self.push_steps(0, &mut steps);
+        steps
+    }
+
+    /// Pushes the steps for this derivation, starting at `position`, and returns the
+    /// number of atoms it makes.
+    fn push_steps(&self, position: usize, steps: &mut Vec<Step>) -> usize {
+        let Some(rule) = self.rule else {
+            return 1;
+        };
+        steps.push(Step { rule, position });
+        self.children.iter().fold(0, |made, child| {
+            made + child.push_steps(position + made, steps)
         })
-        // The examples don't have those, so just default to Empty.
-        .unwrap_or((Atom::Empty, Atom::Empty));
+    }
 
-    // If there is a rule `e => X` (not `e => XX`), we don't have to subtract one.
-    let empty_to_singles = input
-        .rules
-        .iter()
-        .any(|r| r.source == Atom::Empty && r.target.len() == 1);
+    /// Draws the derivation as a tree, with one line per atom, indented under the atom
+    /// it was made from.
+    fn tree(&self, atoms: &AtomTable) -> String {
+        let mut output = String::new();
+        self.write_tree(atoms, 0, &mut output);
+        output
+    }
 
-    let mut count = 0;
-    for &atom in &input.target {
-        if atom == open || atom == separator {
-            count -= 1;
-        } else {
-            count += 1;
+    fn write_tree(&self, atoms: &AtomTable, depth: usize, output: &mut String) {
+        let _ = write!(
+            output,
+            "{:indent$}{}",
+            "",
+            atoms.name(self.atom),
+            indent = 2 * depth
+        );
+        if self.rule.is_some() {
+            output.push_str(" => ");
+            for child in &self.children {
+                output.push_str(atoms.name(child.atom));
+            }
+        }
+        for child in &self.children {
+            output.push('\n');
+            child.write_tree(atoms, depth + 1, output);
         }
     }
-    if !empty_to_singles {
-        count -= 1;
+}
+
+/// Applies the steps to `e`, and returns the molecule after each step.
+fn replay(schema: &Schema, steps: &[Step]) -> Result<Vec<Vec<Atom>>, ReplayError> {
+    let mut molecule = vec![Atom::EMPTY];
+    let mut molecules = Vec::with_capacity(steps.len());
+    for (step, &Step { rule, position }) in (1..).zip(steps) {
+        let rule = schema
+            .rules
+            .get(rule)
+            .ok_or(ReplayError::UnknownRule { step, rule })?;
+        let &found = molecule
+            .get(position)
+            .ok_or(ReplayError::OutOfRange { step, position })?;
+        if found != rule.source {
+            return Err(ReplayError::WrongAtom {
+                step,
+                expected: schema.atoms.name(rule.source).to_string(),
+                found: schema.atoms.name(found).to_string(),
+            });
+        }
+        molecule.splice(position..=position, rule.target.iter().copied());
+        molecules.push(molecule.clone());
+    }
+    Ok(molecules)
+}
+
+/// Marks a span that cannot be derived from an atom.
+const UNDERIVABLE: u32 = u32::MAX;
+
+/// The fewest rule applications needed to derive every span of the target molecule from
+/// every atom, treating the rules as a context-free grammar.
+///
+/// Filled in CYK style, from the last start position backwards. The fewest steps to
+/// erase each atom, deriving nothing, are worked out first and fill the empty spans. A
+/// rule that derives a span with at least two of its atoms only depends on shorter spans.
+/// One that derives it with a single atom, erasing the others, depends on the same span,
+/// and is relaxed until nothing changes.
+struct Chart {
+    /// The number of atoms in the schema. The other fields index atoms by their number.
+    atom_count: usize,
+    rules: Vec<(usize, Vec<usize>)>,
+    molecule: Vec<usize>,
+    len: usize,
+    steps: Vec<u32>,
+}
+
+impl Chart {
+    fn new(schema: &Schema) -> Self {
+        let index = |atoms: &[Atom]| atoms.iter().map(|atom| atom.0 as usize).collect();
+        let rules = schema
+            .rules
+            .iter()
+            .map(|rule| (rule.source.0 as usize, index(&rule.target)))
+            .collect();
+        let molecule: Vec<usize> = index(&schema.target);
+
+        let mut chart = Self {
+            atom_count: schema.atoms.len(),
+            rules,
+            len: molecule.len(),
+            molecule,
+            steps: Vec::new(),
+        };
+        chart.fill();
+        chart
+    }
+
+    const fn cell(&self, start: usize, end: usize, atom: usize) -> usize {
+        (start * (self.len + 1) + end) * self.atom_count + atom
+    }
+
+    /// The fewest steps to derive the molecule's atoms `start..end` from `atom`.
+    fn steps(&self, start: usize, end: usize, atom: usize) -> Option<u32> {
+        Some(self.steps[self.cell(start, end, atom)]).filter(|&steps| steps != UNDERIVABLE)
+    }
+
+    fn steps_from_empty(&self) -> Result<u32, DerivationError> {
+        self.steps(0, self.len, 0)
+            .ok_or(DerivationError::Underivable)
+    }
+
+    /// A derivation of the molecule from `e` with the fewest steps.
+    fn derivation(&self) -> Result<Derivation, DerivationError> {
+        self.derive(0, self.len, 0)
+            .ok_or(DerivationError::Underivable)
+    }
+
+    /// Follows the chart back down from the span `start..end` derived from `atom`,
+    /// choosing at each level a rule and split that account for all of its steps.
+    fn derive(&self, start: usize, end: usize, atom: usize) -> Option<Derivation> {
+        let steps = self.steps(start, end, atom)?;
+        if steps == 0 {
+            return Some(Derivation {
+                atom: Atom(u32::try_from(atom).expect("too many atoms")),
+                rule: None,
+                children: Vec::new(),
+            });
+        }
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(source, _))| source == atom)
+            .find_map(|(rule, (_, target))| {
+                let mut bounds = vec![start];
+                if !self.split(target, end, steps - 1, &mut bounds) {
+                    return None;
+                }
+                let children = target
+                    .iter()
+                    .zip(bounds.windows(2))
+                    .map(|(&child, bounds)| self.derive(bounds[0], bounds[1], child))
+                    .collect::<Option<_>>()?;
+                Some(Derivation {
+                    atom: Atom(u32::try_from(atom).expect("too many atoms")),
+                    rule: Some(rule),
+                    children,
+                })
+            })
+    }
+
+    /// Finds where each atom of `target` ends, continuing from the last of `bounds`, so
+    /// that together they derive the molecule up to `end` in exactly `steps` steps.
+    fn split(&self, target: &[usize], end: usize, steps: u32, bounds: &mut Vec<usize>) -> bool {
+        let Some(&start) = bounds.last() else {
+            return false;
+        };
+        match target {
+            [] => start == end && steps == 0,
+            &[last] => {
+                let fits = self.steps(start, end, last) == Some(steps);
+                if fits {
+                    bounds.push(end);
+                }
+                fits
+            }
+            &[first, ref rest @ ..] => {
+                for middle in start..=end {
+                    let Some(first_steps) = self.steps(start, middle, first) else {
+                        continue;
+                    };
+                    let Some(rest_steps) = steps.checked_sub(first_steps) else {
+                        continue;
+                    };
+                    bounds.push(middle);
+                    if self.split(rest, end, rest_steps, bounds) {
+                        return true;
+                    }
+                    bounds.pop();
+                }
+                false
+            }
+        }
+    }
+
+    /// The fewest steps to derive nothing from each atom, relaxing the rules until
+    /// nothing changes.
+    fn erase_steps(&self) -> Vec<u32> {
+        let mut erase = vec![UNDERIVABLE; self.atom_count];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (source, target) in &self.rules {
+                let steps = target
+                    .iter()
+                    .fold(1, |sum: u32, &atom| sum.saturating_add(erase[atom]));
+                if steps < erase[*source] {
+                    erase[*source] = steps;
+                    changed = true;
+                }
+            }
+        }
+        erase
+    }
+
+    /// The rules that can derive a span from a single one of their atoms, erasing the
+    /// others, as (source, atom, steps to apply the rule and erase the others).
+    fn unit_rules(&self, erase: &[u32]) -> Vec<(usize, usize, u32)> {
+        let mut units = Vec::new();
+        for (source, target) in &self.rules {
+            for (kept, &atom) in target.iter().enumerate() {
+                let steps = target
+                    .iter()
+                    .enumerate()
+                    .filter(|&(other, _)| other != kept)
+                    .fold(1, |sum: u32, (_, &other)| sum.saturating_add(erase[other]));
+                if steps != UNDERIVABLE {
+                    units.push((*source, atom, steps));
+                }
+            }
+        }
+        units
+    }
+
+    /// Applies the unit rules to the span `start..end` until nothing changes.
+    fn relax_units(&mut self, start: usize, end: usize, units: &[(usize, usize, u32)]) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(source, atom, rule_steps) in units {
+                let steps = self.steps[self.cell(start, end, atom)];
+                let cell = self.cell(start, end, source);
+                if steps != UNDERIVABLE && steps.saturating_add(rule_steps) < self.steps[cell] {
+                    self.steps[cell] = steps + rule_steps;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn fill(&mut self) {
+        let len = self.len;
+        self.steps = vec![UNDERIVABLE; (len + 1) * (len + 1) * self.atom_count];
+
+        let erase = self.erase_steps();
+        for start in 0..=len {
+            for (atom, &steps) in erase.iter().enumerate() {
+                let cell = self.cell(start, start, atom);
+                self.steps[cell] = steps;
+            }
+        }
+
+        let units = self.unit_rules(&erase);
+
+        // For each rule with several atoms, the fewest steps to derive a span from each
+        // prefix of its atoms. Only spans from the current start are kept.
+        let mut offsets = Vec::with_capacity(self.rules.len());
+        let mut width = 0;
+        // The (rule, prefix length) pairs that end in each atom, past the first atom.
+        let mut continuations = vec![Vec::new(); self.atom_count];
+        // The steps to erase the atoms before and after each prefix of each rule.
+        let mut erased_before = Vec::new();
+        let mut erased_after = Vec::new();
+        for (rule, (_, target)) in self.rules.iter().enumerate() {
+            offsets.push(width);
+            if target.len() > 1 {
+                width += target.len();
+                for (taken, &atom) in target.iter().enumerate().skip(1) {
+                    continuations[atom].push((rule, taken + 1));
+                }
+                for taken in 1..=target.len() {
+                    let erased = |atoms: &[usize]| {
+                        atoms
+                            .iter()
+                            .fold(0, |sum: u32, &atom| sum.saturating_add(erase[atom]))
+                    };
+                    erased_before.push(erased(&target[..taken - 1]));
+                    erased_after.push(erased(&target[taken..]));
+                }
+            }
+        }
+
+        for start in (0..len).rev() {
+            let mut prefixes = vec![UNDERIVABLE; (len - start + 1) * width];
+            for end in start + 1..=len {
+                if end == start + 1 {
+                    let cell = self.cell(start, end, self.molecule[start]);
+                    self.steps[cell] = 0;
+                }
+
+                // Extend the prefixes ending at `middle` by an atom spanning the rest. The
+                // prefix covers at least one atom of the molecule, so this finds the ways
+                // to derive the span with at least two atoms of the rule.
+                let row = (end - start) * width;
+                for middle in start + 1..end {
+                    let before_row = (middle - start) * width;
+                    for (atom, rules) in continuations.iter().enumerate() {
+                        let Some(last) = self.steps(middle, end, atom) else {
+                            continue;
+                        };
+                        for &(rule, taken) in rules {
+                            let before = prefixes[before_row + offsets[rule] + taken - 2];
+                            if before != UNDERIVABLE {
+                                let slot = row + offsets[rule] + taken - 1;
+                                prefixes[slot] = prefixes[slot].min(before.saturating_add(last));
+                            }
+                        }
+                    }
+                }
+                for (rule, (source, target)) in self.rules.iter().enumerate() {
+                    if target.len() > 1 {
+                        let best = (2..=target.len())
+                            .map(|taken| {
+                                let slot = offsets[rule] + taken - 1;
+                                prefixes[row + slot].saturating_add(erased_after[slot])
+                            })
+                            .min()
+                            .unwrap_or(UNDERIVABLE);
+                        let cell = self.cell(start, end, *source);
+                        if best != UNDERIVABLE && best + 1 < self.steps[cell] {
+                            self.steps[cell] = best + 1;
+                        }
+                    }
+                }
+
+                self.relax_units(start, end, &units);
+
+                // Complete the prefixes with those whose last atom derives the whole
+                // span, or nothing.
+                for (rule, (_, target)) in self.rules.iter().enumerate() {
+                    if target.len() > 1 {
+                        for (taken, &atom) in (1..).zip(target) {
+                            let slot = offsets[rule] + taken - 1;
+                            let whole = self.steps[self.cell(start, end, atom)]
+                                .saturating_add(erased_before[slot]);
+                            let erased = if taken > 1 {
+                                prefixes[row + slot - 1].saturating_add(erase[atom])
+                            } else {
+                                UNDERIVABLE
+                            };
+                            prefixes[row + slot] = prefixes[row + slot].min(whole).min(erased);
+                        }
+                    }
+                }
+            }
+        }
     }
-    count
 }
 
 #[cfg(test)]
@@ -177,20 +1019,49 @@ mod tests {
             };
         }
 
+        assert_eq!(result.atoms.names, ["e", "H", "O"]);
+        let [e, h, o] = [0, 1, 2].map(Atom);
         assert_eq!(
             result.rules,
             &[
-                rule!(Atom::Empty => Atom::Single(b'H')),
-                rule!(Atom::Empty => Atom::Single(b'O')),
-                rule!(Atom::Single(b'H') => Atom::Single(b'H'), Atom::Single(b'O')),
-                rule!(Atom::Single(b'H') => Atom::Single(b'O'), Atom::Single(b'H')),
-                rule!(Atom::Single(b'O') => Atom::Single(b'H'), Atom::Single(b'H')),
+                rule!(e => h),
+                rule!(e => o),
+                rule!(h => h, o),
+                rule!(h => o, h),
+                rule!(o => h, h),
             ]
         );
-        assert_eq!(
-            result.target,
-            &[Atom::Single(b'H'), Atom::Single(b'O'), Atom::Single(b'H')]
-        );
+        assert_eq!(result.target, &[h, o, h]);
+    }
+
+    #[test_case(b"HOH" => Some("H O H".to_string()))]
+    #[test_case(b"CaSiRnAr" => Some("Ca Si Rn Ar".to_string()))]
+    #[test_case(b"UueC12O2" => Some("Uue C12 O2".to_string()))]
+    #[test_case(b"eHe" => Some("e He".to_string()))]
+    #[test_case(b"" => Some(String::new()))]
+    #[test_case(b"ex" => None)]
+    #[test_case(b"2H" => None)]
+    #[test_case(b"H O" => None)]
+    fn test_tokenize(text: &[u8]) -> Option<String> {
+        let mut atoms = AtomTable::default();
+        let molecule = atoms.tokenize(text).ok()?;
+        let names: Vec<_> = molecule.iter().map(|&atom| atoms.name(atom)).collect();
+        Some(names.join(" "))
+    }
+
+    /// Steps a linear congruential generator, so the random tests are repeatable, and
+    /// returns a number below `bound`.
+    fn random_below(seed: &mut u64, bound: usize) -> usize {
+        *seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        usize::try_from((*seed >> 33) % bound as u64).unwrap()
+    }
+
+    #[test]
+    fn test_long_element_names() {
+        let schema = parse(b"e => H2O\nH2 => HH\nO => OxO\n\nHHOxO").unwrap();
+        // The molecule is H, H, Ox, O, so only the last atom can be replaced.
+        assert_eq!(part_1(&schema), 1);
+        assert_eq!(part_2(&schema), Ok(3));
     }
 
     #[test_case(EXAMPLE1 => 4)]
@@ -199,10 +1070,270 @@ mod tests {
         part_1(&schema)
     }
 
-    #[test_case(EXAMPLE1 => 3)]
-    #[test_case(EXAMPLE2 => 6)]
-    fn test_part_2(input: &[u8]) -> usize {
+    #[test_case(EXAMPLE1 => Ok(3))]
+    #[test_case(EXAMPLE2 => Ok(6))]
+    #[test_case(b"e => AB\nA => CC\nB => C\n\nCCC" => Ok(3); "not puzzle shaped")]
+    #[test_case(b"e => A\nA => B\nB => A\nB => CC\n\nCC" => Ok(3); "cyclic single atom rules")]
+    #[test_case(b"e => H\nH => HO\n\nOH" => Err(DerivationError::Underivable); "underivable")]
+    #[test_case(b"e => H\nH => HO\n\nHX" => Err(DerivationError::Underivable); "atom without rules")]
+    #[test_case(ERASING[0] => Ok(2); "erased atom")]
+    #[test_case(ERASING[1] => Ok(5); "erased in several steps")]
+    #[test_case(ERASING[2] => Ok(2); "empty molecule")]
+    #[test_case(ERASING[3] => Ok(3); "erased between atoms")]
+    #[test_case(ERASING[4] => Ok(3); "erased or derived")]
+    fn test_part_2(input: &[u8]) -> Result<u32, DerivationError> {
         let schema = parse(input).unwrap();
         part_2(&schema)
     }
+
+    /// Schemas with rules that erase an atom.
+    const ERASING: [&[u8]; 5] = [
+        b"e => HX\nX => \n\nH",
+        b"e => XHX\nX => Y\nY => \n\nH",
+        b"e => X\nX => \n\n",
+        b"e => AXB\nX => \nA => C\n\nCB",
+        b"e => HY\nY => \nY => Z\nZ => H\nH => HH\n\nHH",
+    ];
+
+    #[test]
+    fn test_part_2_random() {
+        const MAX_STEPS: usize = 6;
+        let mut seed = 1_u64;
+        let mut random = |bound| random_below(&mut seed, bound);
+        for _ in 0..2000 {
+            let mut atoms = AtomTable::default();
+            let alphabet = ["e", "A", "B", "C"].map(|name| atoms.intern(name));
+            let rules: Vec<Rule> = (0..=random(6))
+                .map(|_| Rule {
+                    source: alphabet[random(4)],
+                    target: (0..random(4)).map(|_| alphabet[1 + random(3)]).collect(),
+                })
+                .collect();
+            let target: Vec<Atom> = (0..random(5)).map(|_| alphabet[1 + random(3)]).collect();
+            let expected = steps_to_reach(&rules, &[Atom::EMPTY], &target, MAX_STEPS);
+            let schema = Schema {
+                atoms,
+                rules,
+                target,
+            };
+            let steps = part_2(&schema).ok().map(|steps| steps as usize);
+            match expected {
+                Some(_) => assert_eq!(steps, expected, "{schema:?}"),
+                None => assert!(steps.is_none_or(|steps| steps > MAX_STEPS), "{schema:?}"),
+            }
+            if let Ok(derivation) = Chart::new(&schema).derivation() {
+                let molecules = replay(&schema, &derivation.steps()).unwrap();
+                assert_eq!(
+                    molecules.last().map_or(&[Atom::EMPTY][..], Vec::as_slice),
+                    schema.target
+                );
+            }
+        }
+    }
+
+    #[test_case(EXAMPLE1)]
+    #[test_case(EXAMPLE2)]
+    #[test_case(b"e => AB\nA => CC\nB => C\n\nCCC"; "not puzzle shaped")]
+    #[test_case(b"e => A\nA => B\nB => A\nB => CC\n\nCC"; "cyclic single atom rules")]
+    #[test_case(ERASING[0]; "erased atom")]
+    #[test_case(ERASING[1]; "erased in several steps")]
+    #[test_case(ERASING[2]; "empty molecule")]
+    #[test_case(ERASING[3]; "erased between atoms")]
+    fn test_derivation(input: &[u8]) {
+        let schema = parse(input).unwrap();
+        let derivation = Chart::new(&schema).derivation().unwrap();
+        let steps = derivation.steps();
+        assert_eq!(Ok(u32::try_from(steps.len()).unwrap()), part_2(&schema));
+        let molecules = replay(&schema, &steps).unwrap();
+        assert_eq!(molecules.last(), Some(&schema.target));
+    }
+
+    #[test]
+    fn test_derivation_output() {
+        let schema = parse(EXAMPLE1).unwrap();
+        let output = part_2_derivation(&schema).unwrap();
+        assert_eq!(
+            output,
+            "\n\
+            e => O\n\
+            \x20 O => HH\n\
+            \x20   H\n\
+            \x20   H => OH\n\
+            \x20     O\n\
+            \x20     H\n\
+            1: e => O at 0: O\n\
+            2: O => HH at 0: HH\n\
+            3: H => OH at 1: HOH\
+            "
+        );
+    }
+
+    #[test_case(EXAMPLE1 => 4)]
+    #[test_case(b"H => HH\n\nHHHH" => 1; "periodic run")]
+    #[test_case(b"A => AC\nB => CB\n\nACCB" => 1; "far apart")]
+    #[test_case(b"A => AC\nB => CB\n\nACDCB" => 2; "broken period")]
+    #[test_case(b"A => AB\nB => BB\nB => AB\n\nABB" => 3; "overlapping")]
+    #[test_case(b"H => \n\nHHOHH" => 2; "removal")]
+    #[test_case(b"H => H\nO => O\n\nHOH" => 1; "no change")]
+    #[test_case(b"H => HO\nH => HO\n\nHH" => 2; "repeated rule")]
+    fn test_count_single_replacements(input: &[u8]) -> usize {
+        let schema = parse(input).unwrap();
+        count_single_replacements(&schema.rules, &schema.target)
+    }
+
+    #[test]
+    fn test_count_single_replacements_random() {
+        let mut seed = 1_u64;
+        let mut random = |bound| random_below(&mut seed, bound);
+        for _ in 0..2000 {
+            let mut atoms = AtomTable::default();
+            let alphabet = ["A", "B", "C"].map(|name| atoms.intern(name));
+            let rules: Vec<Rule> = (0..=random(5))
+                .map(|_| Rule {
+                    source: alphabet[random(3)],
+                    target: (0..random(5)).map(|_| alphabet[random(3)]).collect(),
+                })
+                .collect();
+            let molecule: Vec<Atom> = (0..random(13)).map(|_| alphabet[random(3)]).collect();
+            assert_eq!(
+                count_single_replacements(&rules, &molecule),
+                count_reachable_naively(&rules, &molecule, 1).exactly,
+                "{rules:?} {molecule:?}"
+            );
+        }
+    }
+
+    /// Compares the running time of both ways to count single replacements, on a
+    /// molecule of several thousand atoms. Run with `--ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_count_single_replacements() {
+        let rules = b"\
+            e => HF\ne => NAl\ne => OMg\nAl => ThF\nAl => ThRnFAr\nB => BCa\nB => TiB\n\
+            B => TiRnFAr\nCa => CaCa\nCa => PB\nCa => PRnFAr\nCa => SiRnFYFAr\n\
+            Ca => SiRnMgAr\nCa => SiTh\nF => CaF\nF => PMg\nF => SiAl\nH => CRnAlAr\n\
+            H => CRnFYFYFAr\nH => CRnFYMgAr\nH => CRnMgYFAr\nH => HCa\nH => NRnFYFAr\n\
+            H => NRnMgAr\nH => NTh\nH => OB\nH => ORnFAr\nMg => BF\nMg => TiMg\n\
+            N => CRnFAr\nN => HSi\nO => CRnFYFAr\nO => CRnMgAr\nO => HP\nO => NRnFAr\n\
+            O => OTi\nP => CaP\nP => PTi\nP => SiRnFAr\nSi => CaSi\nTh => ThCa\n\
+            Ti => BP\nTi => TiTi\n\
+            \n\
+            e";
+        let schema = parse(rules).unwrap();
+        let mut molecule = vec![Atom::EMPTY];
+        let mut seed = 1_u64;
+        while molecule.len() < 5000 {
+            let position = random_below(&mut seed, molecule.len());
+            let options: Vec<_> = schema
+                .rules
+                .iter()
+                .filter(|rule| rule.source == molecule[position])
+                .collect();
+            if let Some(rule) = options.get(random_below(&mut seed, options.len().max(1))) {
+                molecule = replace(&molecule, position, rule);
+            }
+        }
+        let schema = Schema {
+            target: molecule,
+            ..schema
+        };
+
+        let start = std::time::Instant::now();
+        let built = part_1(&schema);
+        let built_time = start.elapsed();
+        let start = std::time::Instant::now();
+        let analytic = part_1_analytic(&schema);
+        let analytic_time = start.elapsed();
+        println!(
+            "{} atoms: {built} in {built_time:?} building molecules, {analytic} in {analytic_time:?} analytically",
+            schema.target.len()
+        );
+        assert_eq!(built, analytic);
+    }
+
+    /// Counts reachable molecules by building every one of them.
+    fn count_reachable_naively(rules: &[Rule], start: &[Atom], steps: usize) -> ReachCounts {
+        let mut layer = HashSet::from([start.to_vec()]);
+        let mut reached = layer.clone();
+        for _ in 0..steps {
+            let mut next = HashSet::new();
+            for molecule in &layer {
+                for (position, &atom) in molecule.iter().enumerate() {
+                    for rule in rules.iter().filter(|rule| rule.source == atom) {
+                        next.insert(replace(molecule, position, rule));
+                    }
+                }
+            }
+            reached.extend(next.iter().cloned());
+            layer = next;
+        }
+        ReachCounts {
+            exactly: layer.len(),
+            at_most: reached.len(),
+        }
+    }
+
+    #[test_case(EXAMPLE1, 0 => ReachCounts { exactly: 1, at_most: 1 })]
+    #[test_case(EXAMPLE1, 1 => ReachCounts { exactly: 4, at_most: 5 })]
+    #[test_case(EXAMPLE1, 2 => ReachCounts { exactly: 11, at_most: 16 })]
+    #[test_case(EXAMPLE2, 3 => ReachCounts { exactly: 93, at_most: 130 })]
+    fn test_count_reachable(input: &[u8], steps: usize) -> ReachCounts {
+        let schema = parse(input).unwrap();
+        let counts = count_reachable(&schema.rules, &schema.target, steps);
+        assert_eq!(
+            counts,
+            count_reachable_naively(&schema.rules, &schema.target, steps)
+        );
+        counts
+    }
+
+    #[test_case(EXAMPLE1, 3 => Some(3))]
+    #[test_case(EXAMPLE1, 2 => None)]
+    #[test_case(EXAMPLE2, 6 => Some(6))]
+    #[test_case(EXAMPLE2, 5 => None)]
+    #[test_case(b"e => H\nH => HO\nO => \n\nH", 3 => Some(1); "shrinking rule")]
+    fn test_steps_to_reach(input: &[u8], max_steps: usize) -> Option<usize> {
+        let schema = parse(input).unwrap();
+        steps_to_reach(&schema.rules, &[Atom::EMPTY], &schema.target, max_steps)
+    }
+
+    #[test]
+    fn test_polynomial_hash() {
+        let mut hash = PolynomialHash::new(0x0123_4567_89ab_cdef);
+        let molecule = [3, 1, 4, 1, 5, 9, 2, 6].map(Atom);
+        let prefixes = hash.prefixes(&molecule);
+        assert_eq!(prefixes[molecule.len()], hash.of(&molecule));
+        assert_eq!(hash.slice(&prefixes, 2, 6), hash.of(&molecule[2..6]));
+        let joined = hash.concat(hash.of(&molecule[..3]), hash.of(&molecule[3..]), 5);
+        assert_eq!(joined, hash.of(&molecule));
+
+        let (a, b) = (MERSENNE_61 - 1, MERSENNE_61 - 2);
+        let expected = u128::from(a) * u128::from(b) % u128::from(MERSENNE_61);
+        assert_eq!(u128::from(mul_mod(a, b)), expected);
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let schema = parse(EXAMPLE1).unwrap();
+        let step = |rule, position| Step { rule, position };
+        assert_eq!(
+            replay(&schema, &[step(0, 0), step(5, 0)]),
+            Err(ReplayError::UnknownRule { step: 2, rule: 5 })
+        );
+        assert_eq!(
+            replay(&schema, &[step(0, 0), step(2, 1)]),
+            Err(ReplayError::OutOfRange {
+                step: 2,
+                position: 1
+            })
+        );
+        assert_eq!(
+            replay(&schema, &[step(0, 0), step(4, 0)]),
+            Err(ReplayError::WrongAtom {
+                step: 2,
+                expected: "O".to_string(),
+                found: "H".to_string(),
+            })
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use std::fmt::Display;
 use std::num::ParseIntError;
 use std::ops::AddAssign;
 use std::str::FromStr;
@@ -54,17 +55,43 @@ impl FromStr for CharInfo {
 }
 
 impl CharInfo {
+    /// `self` attacks first, so a tie in turns goes to `self`.
     fn beats(&self, enemy: &Self) -> bool {
-        let enemy_kills_self = self
-            .hit_points
-            .div_ceil(enemy.damage.saturating_sub(self.armor).max(1));
-        let self_kills_enemy = enemy
+        self.turns_to_kill(enemy) <= enemy.turns_to_kill(self)
+    }
+
+    /// Attacks needed to bring `enemy` to zero hit points.
+    fn turns_to_kill(&self, enemy: &Self) -> u64 {
+        enemy
             .hit_points
-            .div_ceil(self.damage.saturating_sub(enemy.armor).max(1));
-        self_kills_enemy <= enemy_kills_self
+            .div_ceil(self.damage.saturating_sub(enemy.armor).max(1))
     }
 
-    const fn create_player(equipment: &Item) -> Self {
+    /// Fights `enemy` round by round, with `self` as the player attacking first, until
+    /// someone is out of hit points. The last attack is by the winner.
+    fn fight(&self, enemy: &Self) -> Vec<Attack> {
+        let mut hit_points = [self.hit_points, enemy.hit_points];
+        let fighters = [self, enemy];
+        let mut attacks = Vec::new();
+        for (attacker, defender) in [(0, 1), (1, 0)].into_iter().cycle() {
+            let damage = fighters[attacker].damage;
+            let armor = fighters[defender].armor;
+            hit_points[defender] =
+                hit_points[defender].saturating_sub(damage.saturating_sub(armor).max(1));
+            attacks.push(Attack {
+                by_player: attacker == 0,
+                damage,
+                armor,
+                hit_points_left: hit_points[defender],
+            });
+            if hit_points[defender] == 0 {
+                break;
+            }
+        }
+        attacks
+    }
+
+    const fn create_player(equipment: &Equipment) -> Self {
         Self {
             hit_points: 100,
             damage: equipment.damage,
@@ -73,105 +100,410 @@ impl CharInfo {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// One attack in a fight, displayed the way the puzzle narrates it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attack {
+    by_player: bool,
+    damage: u64,
+    armor: u64,
+    hit_points_left: u64,
+}
+
+impl Display for Attack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (attacker, defender) = if self.by_player {
+            ("player", "boss")
+        } else {
+            ("boss", "player")
+        };
+        write!(
+            f,
+            "The {attacker} deals {}-{} = {} damage; the {defender} goes down to {} hit points.",
+            self.damage,
+            self.armor,
+            self.damage.saturating_sub(self.armor).max(1),
+            self.hit_points_left
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Item {
+    name: String,
     cost: u64,
     damage: u64,
     armor: u64,
 }
 
-impl Item {
-    pub const fn new(cost: u64, damage: u64, armor: u64) -> Self {
-        Self {
+impl FromStr for Item {
+    type Err = ParseError;
+
+    /// Parses a row of the shop table, e.g. `Damage +1    25     1       0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().collect::<Vec<_>>();
+        let [.., cost, damage, armor] = words[..] else {
+            return Err(ParseError::SyntaxError);
+        };
+        let (cost, damage, armor) = (cost.parse()?, damage.parse()?, armor.parse()?);
+        words.truncate(words.len() - 3);
+        if words.is_empty() {
+            return Err(ParseError::SyntaxError);
+        }
+        Ok(Self {
+            name: words.join(" "),
             cost,
             damage,
             armor,
-        }
+        })
     }
+}
 
-    fn from_config(mut config: usize) -> Self {
-        let mut equipment = Self::default();
+/// The combined stats of a set of items.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Equipment {
+    cost: u64,
+    damage: u64,
+    armor: u64,
+}
 
-        let weapon_index = config % 5;
-        equipment += WEAPONS[weapon_index];
-        config /= 5;
+impl Equipment {
+    /// Whether `self` is at least as good as `other` in every way, and better in one.
+    fn dominates(&self, other: &Self) -> bool {
+        self.cost <= other.cost
+            && self.damage >= other.damage
+            && self.armor >= other.armor
+            && self != other
+    }
+}
 
-        let armor_index = config % 6;
-        if armor_index != 0 {
-            equipment += ARMOR[armor_index - 1];
+impl AddAssign<&Item> for Equipment {
+    fn add_assign(&mut self, rhs: &Item) {
+        self.cost += rhs.cost;
+        self.damage += rhs.damage;
+        self.armor += rhs.armor;
+    }
+}
+
+/// A section of the shop, from which between `min` and `max` different items are bought.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slot {
+    name: String,
+    min: usize,
+    max: usize,
+    items: Vec<Item>,
+}
+
+impl Slot {
+    /// The puzzle's limits: exactly one weapon, at most one armor and at most two rings.
+    fn default_limits(name: &str) -> (usize, usize) {
+        match name {
+            "Weapons" => (1, 1),
+            "Rings" => (0, 2),
+            _ => (0, 1),
         }
-        config /= 6;
+    }
 
-        for (ring_index, ring) in RINGS.iter().enumerate() {
-            if (config >> ring_index) & 1 != 0 {
-                equipment += *ring;
+    /// Every allowed choice of items from this slot.
+    fn choices(&self) -> Vec<Vec<&Item>> {
+        let mut choices = vec![vec![]];
+        for item in &self.items {
+            for index in 0..choices.len() {
+                if choices[index].len() < self.max {
+                    let mut choice = choices[index].clone();
+                    choice.push(item);
+                    choices.push(choice);
+                }
             }
         }
+        choices.retain(|choice| choice.len() >= self.min);
+        choices
+    }
+}
 
-        equipment
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Shop {
+    slots: Vec<Slot>,
+}
+
+impl FromStr for Shop {
+    type Err = ParseError;
+
+    /// Parses the shop table from the puzzle. Each section starts with a header like
+    /// `Rings:      Cost  Damage  Armor`. The slot limits can be given in the header as
+    /// `Rings [0-2]:`, and otherwise follow the puzzle's rules.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut slots = Vec::<Slot>::new();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            if let Some((name, columns)) = line.split_once(':') {
+                if !columns.split_whitespace().eq(["Cost", "Damage", "Armor"]) {
+                    return Err(ParseError::SyntaxError);
+                }
+                let (name, (min, max)) = match name.split_once(" [") {
+                    Some((name, limits)) => {
+                        let (min, max) = limits
+                            .strip_suffix(']')
+                            .and_then(|limits| limits.split_once('-'))
+                            .ok_or(ParseError::SyntaxError)?;
+                        (name, (min.parse()?, max.parse()?))
+                    }
+                    None => (name, Slot::default_limits(name)),
+                };
+                slots.push(Slot {
+                    name: name.to_string(),
+                    min,
+                    max,
+                    items: Vec::new(),
+                });
+            } else {
+                let slot = slots.last_mut().ok_or(ParseError::SyntaxError)?;
+                slot.items.push(line.parse()?);
+            }
+        }
+        Ok(Self { slots })
     }
 }
 
-impl AddAssign for Item {
-    fn add_assign(&mut self, rhs: Self) {
-        self.cost += rhs.cost;
-        self.damage += rhs.damage;
-        self.armor += rhs.armor;
+impl Shop {
+    /// Every combination of items allowed by the slot limits.
+    fn loadouts(&self) -> Vec<Loadout<'_>> {
+        self.slots
+            .iter()
+            .fold(vec![Loadout::default()], |loadouts, slot| {
+                let choices = slot.choices();
+                loadouts
+                    .iter()
+                    .flat_map(|loadout| {
+                        choices.iter().map(move |choice| {
+                            let mut loadout = loadout.clone();
+                            loadout.slots.push((&slot.name, choice.clone()));
+                            loadout
+                        })
+                    })
+                    .collect()
+            })
     }
 }
 
-const WEAPONS: &[Item] = &[
-    Item::new(8, 4, 0),
-    Item::new(10, 5, 0),
-    Item::new(25, 6, 0),
-    Item::new(40, 7, 0),
-    Item::new(74, 8, 0),
-];
-const ARMOR: &[Item] = &[
-    Item::new(13, 0, 1),
-    Item::new(31, 0, 2),
-    Item::new(53, 0, 3),
-    Item::new(75, 0, 4),
-    Item::new(102, 0, 5),
-];
-const RINGS: &[Item] = &[
-    Item::new(25, 1, 0),
-    Item::new(50, 2, 0),
-    Item::new(100, 3, 0),
-    Item::new(20, 0, 1),
-    Item::new(40, 0, 2),
-    Item::new(80, 0, 3),
-];
+/// The items bought from each slot of the shop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Loadout<'a> {
+    slots: Vec<(&'a str, Vec<&'a Item>)>,
+}
 
-#[aoc_generator(day21)]
-fn parse(input: &str) -> Result<CharInfo, ParseError> {
-    input.parse()
+impl Loadout<'_> {
+    fn equipment(&self) -> Equipment {
+        let mut equipment = Equipment::default();
+        for &item in self.slots.iter().flat_map(|(_, items)| items) {
+            equipment += item;
+        }
+        equipment
+    }
 }
 
-#[aoc(day21, part1)]
-fn part_1(boss: &CharInfo) -> u64 {
-    let mut win_min_cost = u64::MAX;
-    for config in 0..(5 * 6) << 6 {
-        let equipment = Item::from_config(config);
-        let player = CharInfo::create_player(&equipment);
-        if player.beats(boss) {
-            win_min_cost = win_min_cost.min(equipment.cost);
+impl Display for Loadout<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, (slot, items)) in self.slots.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{slot}: ")?;
+            if items.is_empty() {
+                write!(f, "none")?;
+            }
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", item.name)?;
+            }
         }
+        Ok(())
     }
-    win_min_cost
 }
 
-#[aoc(day21, part2)]
-fn part_2(boss: &CharInfo) -> u64 {
-    let mut loss_max_cost = u64::MIN;
-    for config in 0..(5 * 6) << 6 {
-        let equipment = Item::from_config(config);
+/// The fight against the boss with a given loadout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fight<'a> {
+    loadout: Loadout<'a>,
+    equipment: Equipment,
+    /// Turns the player needs to kill the boss.
+    player_turns: u64,
+    /// Turns the boss needs to kill the player.
+    boss_turns: u64,
+    player_wins: bool,
+}
+
+impl<'a> Fight<'a> {
+    fn new(loadout: Loadout<'a>, boss: &CharInfo) -> Self {
+        let equipment = loadout.equipment();
         let player = CharInfo::create_player(&equipment);
-        if !player.beats(boss) {
-            loss_max_cost = loss_max_cost.max(equipment.cost);
+        Self {
+            loadout,
+            equipment,
+            player_turns: player.turns_to_kill(boss),
+            boss_turns: boss.turns_to_kill(&player),
+            player_wins: player.beats(boss),
         }
     }
-    loss_max_cost
+}
+
+impl Display for Fight<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} gold: {} (damage {}, armor {}; player needs {} turns, boss needs {} turns)",
+            self.equipment.cost,
+            self.loadout,
+            self.equipment.damage,
+            self.equipment.armor,
+            self.player_turns,
+            self.boss_turns
+        )
+    }
+}
+
+/// All fights won at the lowest cost, or lost at the highest cost.
+fn optimal_fights<'a>(shop: &'a Shop, boss: &CharInfo, win: bool) -> Vec<Fight<'a>> {
+    let mut best = Vec::<Fight>::new();
+    for loadout in shop.loadouts() {
+        let fight = Fight::new(loadout, boss);
+        if fight.player_wins != win {
+            continue;
+        }
+        let (cost, best_cost) = (
+            fight.equipment.cost,
+            best.first().map(|fight| fight.equipment.cost),
+        );
+        match best_cost {
+            Some(best_cost) if cost == best_cost => best.push(fight),
+            Some(best_cost) if (cost < best_cost) != win => {}
+            _ => best = vec![fight],
+        }
+    }
+    best
+}
+
+/// The shop from the puzzle.
+const SHOP: &str = "\
+    Weapons:    Cost  Damage  Armor\n\
+    Dagger        8     4       0\n\
+    Shortsword   10     5       0\n\
+    Warhammer    25     6       0\n\
+    Longsword    40     7       0\n\
+    Greataxe     74     8       0\n\
+    \n\
+    Armor:      Cost  Damage  Armor\n\
+    Leather      13     0       1\n\
+    Chainmail    31     0       2\n\
+    Splintmail   53     0       3\n\
+    Bandedmail   75     0       4\n\
+    Platemail   102     0       5\n\
+    \n\
+    Rings:      Cost  Damage  Armor\n\
+    Damage +1    25     1       0\n\
+    Damage +2    50     2       0\n\
+    Damage +3   100     3       0\n\
+    Defense +1   20     0       1\n\
+    Defense +2   40     0       2\n\
+    Defense +3   80     0       3\
+";
+
+#[derive(Debug, Clone)]
+struct Battle {
+    boss: CharInfo,
+    shop: Shop,
+}
+
+/// Parses the boss stats, optionally followed by an empty line and a shop table
+/// replacing the puzzle's shop.
+#[aoc_generator(day21)]
+fn parse(input: &str) -> Result<Battle, ParseError> {
+    let (boss, shop) = match input.split_once("\n\n") {
+        Some((boss, shop)) => (boss, shop),
+        None => (input, SHOP),
+    };
+    Ok(Battle {
+        boss: boss.parse()?,
+        shop: shop.parse()?,
+    })
+}
+
+/// Loadouts that no other loadout beats on cost, damage and armor at once, by cost.
+fn pareto_front<'a>(shop: &'a Shop, boss: &CharInfo) -> Vec<Fight<'a>> {
+    let fights = shop
+        .loadouts()
+        .into_iter()
+        .map(|loadout| Fight::new(loadout, boss))
+        .collect::<Vec<_>>();
+    let mut front = fights
+        .iter()
+        .filter(|fight| {
+            !fights
+                .iter()
+                .any(|other| other.equipment.dominates(&fight.equipment))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    front.sort_by_key(|fight| fight.equipment.cost);
+    front
+}
+
+#[aoc(day21, part1)]
+fn part_1(battle: &Battle) -> u64 {
+    optimal_fights(&battle.shop, &battle.boss, true)
+        .first()
+        .map_or(u64::MAX, |fight| fight.equipment.cost)
+}
+
+#[aoc(day21, part2)]
+fn part_2(battle: &Battle) -> u64 {
+    optimal_fights(&battle.shop, &battle.boss, false)
+        .first()
+        .map_or(u64::MIN, |fight| fight.equipment.cost)
+}
+
+#[aoc(day21, part1, loadouts)]
+fn part_1_loadouts(battle: &Battle) -> String {
+    describe_fights(&optimal_fights(&battle.shop, &battle.boss, true))
+}
+
+#[aoc(day21, part2, loadouts)]
+fn part_2_loadouts(battle: &Battle) -> String {
+    describe_fights(&optimal_fights(&battle.shop, &battle.boss, false))
+}
+
+#[aoc(day21, part1, battle_log)]
+fn part_1_battle_log(battle: &Battle) -> String {
+    let Some(fight) = optimal_fights(&battle.shop, &battle.boss, true)
+        .into_iter()
+        .next()
+    else {
+        return "no win".to_string();
+    };
+    let player = CharInfo::create_player(&fight.equipment);
+    let lines = player
+        .fight(&battle.boss)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    format!("{fight}\n{}", lines.join("\n"))
+}
+
+#[aoc(day21, part1, pareto_front)]
+fn part_1_pareto_front(battle: &Battle) -> String {
+    let lines = pareto_front(&battle.shop, &battle.boss)
+        .iter()
+        .map(|fight| {
+            let outcome = if fight.player_wins { "wins" } else { "loses" };
+            format!("{fight} {outcome}")
+        })
+        .collect::<Vec<_>>();
+    format!("\n{}", lines.join("\n"))
+}
+
+fn describe_fights(fights: &[Fight]) -> String {
+    let lines = fights.iter().map(ToString::to_string).collect::<Vec<_>>();
+    format!("\n{}", lines.join("\n"))
 }
 
 #[cfg(test)]
@@ -186,12 +518,190 @@ mod tests {
 
     #[test]
     fn test_parse() {
-        let result = parse(EXAMPLE).unwrap();
+        let result = parse(EXAMPLE).unwrap().boss;
         assert_eq!(result.hit_points, 12);
         assert_eq!(result.damage, 7);
         assert_eq!(result.armor, 2);
     }
 
+    #[test]
+    fn test_parse_shop() {
+        let shop: Shop = SHOP.parse().unwrap();
+        let slots = shop
+            .slots
+            .iter()
+            .map(|slot| (slot.name.as_str(), slot.min, slot.max, slot.items.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            slots,
+            [("Weapons", 1, 1, 5), ("Armor", 0, 1, 5), ("Rings", 0, 2, 6)]
+        );
+        assert_eq!(
+            shop.slots[2].items[1],
+            Item {
+                name: "Damage +2".to_string(),
+                cost: 50,
+                damage: 2,
+                armor: 0,
+            }
+        );
+        // 5 weapons, 6 armor choices and 1 + 6 + 15 ring choices.
+        assert_eq!(shop.loadouts().len(), 5 * 6 * 22);
+    }
+
+    #[test]
+    fn test_optimal_fights() {
+        let battle = parse("Hit Points: 103\nDamage: 9\nArmor: 2").unwrap();
+        let wins = optimal_fights(&battle.shop, &battle.boss, true)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            wins,
+            [
+                "121 gold: Weapons: Longsword; Armor: Chainmail; Rings: Damage +2 \
+                 (damage 9, armor 2; player needs 15 turns, boss needs 15 turns)",
+            ]
+        );
+
+        let battle = parse(
+            "\
+            Hit Points: 2\n\
+            Damage: 100\n\
+            Armor: 0\n\
+            \n\
+            Weapons:    Cost  Damage  Armor\n\
+            Stick         1     1       0\n\
+            \n\
+            Trinkets:   Cost  Damage  Armor\n\
+            Pebble        1     1       0\n\
+            Button        1     1       0\
+            ",
+        )
+        .unwrap();
+        let wins = optimal_fights(&battle.shop, &battle.boss, true)
+            .iter()
+            .map(|fight| fight.loadout.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            wins,
+            [
+                "Weapons: Stick; Trinkets: Pebble",
+                "Weapons: Stick; Trinkets: Button"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fight() {
+        let player = CharInfo {
+            hit_points: 8,
+            damage: 5,
+            armor: 5,
+        };
+        let boss = parse(EXAMPLE).unwrap().boss;
+        let log = player
+            .fight(&boss)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            log,
+            [
+                "The player deals 5-2 = 3 damage; the boss goes down to 9 hit points.",
+                "The boss deals 7-5 = 2 damage; the player goes down to 6 hit points.",
+                "The player deals 5-2 = 3 damage; the boss goes down to 6 hit points.",
+                "The boss deals 7-5 = 2 damage; the player goes down to 4 hit points.",
+                "The player deals 5-2 = 3 damage; the boss goes down to 3 hit points.",
+                "The boss deals 7-5 = 2 damage; the player goes down to 2 hit points.",
+                "The player deals 5-2 = 3 damage; the boss goes down to 0 hit points.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fight_agrees_with_beats() {
+        let shop: Shop = SHOP.parse().unwrap();
+        let players = shop
+            .loadouts()
+            .iter()
+            .map(|loadout| CharInfo::create_player(&loadout.equipment()))
+            .collect::<Vec<_>>();
+        for hit_points in [1, 2, 50, 99, 100, 101, 150] {
+            for damage in 0..=12 {
+                for armor in 0..=6 {
+                    let boss = CharInfo {
+                        hit_points,
+                        damage,
+                        armor,
+                    };
+                    for player in &players {
+                        let attacks = player.fight(&boss);
+                        let player_won = attacks.last().unwrap().by_player;
+                        assert_eq!(player_won, player.beats(&boss), "{player:?} vs {boss:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_slot_limits() {
+        let shop: Shop = SHOP.parse().unwrap();
+        for loadout in shop.loadouts() {
+            let counts = loadout
+                .slots
+                .iter()
+                .map(|(_, items)| items.len())
+                .collect::<Vec<_>>();
+            assert!(matches!(counts[..], [1, 0..=1, 0..=2]), "{loadout}");
+        }
+    }
+
+    #[test]
+    fn test_pareto_front() {
+        let battle = parse("Hit Points: 103\nDamage: 9\nArmor: 2").unwrap();
+        let front = pareto_front(&battle.shop, &battle.boss);
+        let loadouts = battle.shop.loadouts();
+        for loadout in &loadouts {
+            let equipment = loadout.equipment();
+            let on_front = front.iter().any(|fight| fight.loadout == *loadout);
+            let dominated = front
+                .iter()
+                .any(|fight| fight.equipment.dominates(&equipment));
+            assert_ne!(on_front, dominated, "{loadout}");
+        }
+        assert!(front.is_sorted_by_key(|fight| fight.equipment.cost));
+        assert_eq!(front.first().unwrap().equipment.cost, 8);
+        let cheapest_win = front.iter().find(|fight| fight.player_wins).unwrap();
+        assert_eq!(cheapest_win.equipment.cost, part_1(&battle));
+    }
+
+    #[test]
+    fn test_custom_shop() {
+        let battle = parse(
+            "\
+            Hit Points: 30\n\
+            Damage: 25\n\
+            Armor: 0\n\
+            \n\
+            Weapons:    Cost  Damage  Armor\n\
+            Stick         1     1       0\n\
+            Sword        10     9       0\n\
+            \n\
+            Trinkets [0-3]:  Cost  Damage  Armor\n\
+            Pebble        1     1       0\n\
+            Feather       1     0       1\n\
+            Button        1     1       0\
+            ",
+        )
+        .unwrap();
+        assert_eq!(battle.shop.slots[1].max, 3);
+        assert_eq!(battle.shop.loadouts().len(), 2 * 8);
+        assert_eq!(part_1(&battle), 10);
+        assert_eq!(part_2(&battle), 4);
+    }
+
     #[test]
     fn test_simulate() {
         let mut player = CharInfo {
@@ -1,5 +1,7 @@
-use std::collections::{BinaryHeap, HashSet};
-use std::hash::Hash;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
 use std::num::ParseIntError;
 use std::str::FromStr;
 
@@ -9,10 +11,32 @@ use thiserror::Error;
 enum ParseError {
     #[error("Syntax error")]
     SyntaxError,
+    #[error("Unknown property: {0}")]
+    UnknownProperty(String),
+    #[error("Missing player definition")]
+    MissingPlayer,
+    #[error("More than one player definition")]
+    DuplicatePlayer,
+    #[error("No spells defined")]
+    NoSpells,
+    #[error("Spell with armor but no duration")]
+    ArmorWithoutDuration,
     #[error(transparent)]
     InvalidNumber(#[from] ParseIntError),
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+enum ReplayError {
+    #[error("Cannot cast {spell} in round {round}")]
+    CannotCast { round: usize, spell: String },
+    #[error("The battle is already over in round {round}")]
+    BattleOver { round: usize },
+    #[error("The round limit is reached in round {round}")]
+    OutOfRounds { round: usize },
+    #[error("The player cannot win")]
+    NoWin,
+}
+
 fn parse_line<T>(line: Option<&str>, prefix: &str) -> Result<T, ParseError>
 where
     T: FromStr,
@@ -25,75 +49,326 @@ where
         .parse()?)
 }
 
+/// The spell book and starting player stats used by the puzzle.
+const STANDARD_CONFIG: &str = "\
+    Player: 50 hit points, 500 mana\n\
+    Magic Missile: costs 53, 4 damage\n\
+    Drain: costs 73, 2 damage, 2 healing\n\
+    Shield: costs 113, 7 armor, lasts 6\n\
+    Poison: costs 173, 3 damage, lasts 6\n\
+    Recharge: costs 229, 101 mana, lasts 5\
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Config {
+    player: Player,
+    spells: Vec<Spell>,
+}
+
+impl Config {
+    fn standard() -> Self {
+        STANDARD_CONFIG.parse().expect("valid built-in config")
+    }
+}
+
+impl FromStr for Config {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut player = None;
+        let mut spells = Vec::new();
+        for line in s.lines().filter(|line| !line.is_empty()) {
+            let (name, properties) = line.split_once(": ").ok_or(ParseError::SyntaxError)?;
+            if name == "Player" {
+                if player.is_some() {
+                    return Err(ParseError::DuplicatePlayer);
+                }
+                player = Some(properties.parse()?);
+            } else {
+                let mut spell: Spell = properties.parse()?;
+                spell.name = name.to_string();
+                spells.push(spell);
+            }
+        }
+        if spells.is_empty() {
+            return Err(ParseError::NoSpells);
+        }
+        Ok(Self {
+            player: player.ok_or(ParseError::MissingPlayer)?,
+            spells,
+        })
+    }
+}
+
+/// The boss from the puzzle input, and the rules to fight it with.
+#[derive(Debug, Clone)]
+struct Battle {
+    boss: Boss,
+    config: Config,
+}
+
+/// Parses the boss stats, optionally followed by an empty line and a config replacing
+/// the standard spell book and player stats.
 #[aoc_generator(day22)]
-fn parse(input: &str) -> Result<Boss, ParseError> {
-    let mut lines = input.lines();
+fn parse(input: &str) -> Result<Battle, ParseError> {
+    let (boss, config) = match input.split_once("\n\n") {
+        Some((boss, config)) => (boss, config.parse()?),
+        None => (input, Config::standard()),
+    };
+    let mut lines = boss.lines();
     let hit_points = parse_line(lines.next(), "Hit Points: ")?;
     let damage = parse_line(lines.next(), "Damage: ")?;
     if lines.next().is_some() {
         return Err(ParseError::SyntaxError);
     }
-    Ok(Boss::new(hit_points, damage))
+    Ok(Battle {
+        boss: Boss::new(hit_points, damage),
+        config,
+    })
 }
 
 #[aoc(day22, part1)]
-fn part_1(boss: &Boss) -> u64 {
-    Simulator::new(Player::new(50, 500), *boss, false)
-        .map(|s| s.mana_spent)
-        .min()
-        .unwrap()
+fn part_1(battle: &Battle) -> Result<u64, ReplayError> {
+    Simulator::new(
+        battle.config.player,
+        battle.boss,
+        Modifiers::NORMAL,
+        &battle.config.spells,
+    )
+    .map(|s| s.mana_spent)
+    .min()
+    .ok_or(ReplayError::NoWin)
 }
 
 #[aoc(day22, part2)]
-fn part_2(boss: &Boss) -> u64 {
-    Simulator::new(Player::new(50, 500), *boss, true)
-        .map(|s| s.mana_spent)
-        .min()
-        .unwrap()
+fn part_2(battle: &Battle) -> Result<u64, ReplayError> {
+    Simulator::new(
+        battle.config.player,
+        battle.boss,
+        Modifiers::HARD,
+        &battle.config.spells,
+    )
+    .map(|s| s.mana_spent)
+    .min()
+    .ok_or(ReplayError::NoWin)
+}
+
+#[aoc(day22, part1, battle_log)]
+fn part_1_battle_log(battle: &Battle) -> Result<String, ReplayError> {
+    cheapest_battle_log(battle, Modifiers::NORMAL)
+}
+
+#[aoc(day22, part2, battle_log)]
+fn part_2_battle_log(battle: &Battle) -> Result<String, ReplayError> {
+    cheapest_battle_log(battle, Modifiers::HARD)
+}
+
+#[aoc(day22, part1, stats)]
+fn part_1_stats(battle: &Battle) -> String {
+    search_stats(battle, Modifiers::NORMAL)
+}
+
+#[aoc(day22, part2, stats)]
+fn part_2_stats(battle: &Battle) -> String {
+    search_stats(battle, Modifiers::HARD)
+}
+
+fn cheapest_battle_log(battle: &Battle, modifiers: Modifiers) -> Result<String, ReplayError> {
+    let Config { player, spells } = &battle.config;
+    let start = State::new(*player, battle.boss, modifiers, spells);
+    let win = Simulator::from_state(start.clone(), spells)
+        .cheapest_win()
+        .ok_or(ReplayError::NoWin)?;
+    let events = start.replay(&win.spells, spells)?;
+    Ok(format!("{}\n\n{}", narrate(&events), win.mana_spent))
+}
+
+fn search_stats(battle: &Battle, modifiers: Modifiers) -> String {
+    let Config { player, spells } = &battle.config;
+    let mut simulator = Simulator::new(*player, battle.boss, modifiers, spells);
+    match simulator.cheapest_win() {
+        Some(win) => format!("{} ({})", win.mana_spent, simulator.stats()),
+        None => format!("no win ({})", simulator.stats()),
+    }
+}
+
+/// Rule changes on top of the standard battle rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Modifiers {
+    /// Hit points the player loses at the start of each of their turns, before effects.
+    hp_drain: u64,
+    /// Boss damage, in percent of its listed damage.
+    boss_damage_percent: u64,
+    /// Mana the player gains at the start of each of their turns, before effects.
+    mana_regen: u64,
+    /// Extra cost of every spell cast after the first `after_rounds` rounds.
+    surcharge: Option<Surcharge>,
+    /// Rounds the player has to win in.
+    max_rounds: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Surcharge {
+    after_rounds: i64,
+    extra_cost: u64,
+}
+
+impl Modifiers {
+    /// The rules of part 1.
+    const NORMAL: Self = Self {
+        hp_drain: 0,
+        boss_damage_percent: 100,
+        mana_regen: 0,
+        surcharge: None,
+        max_rounds: None,
+    };
+
+    /// The rules of part 2.
+    const HARD: Self = Self {
+        hp_drain: 1,
+        ..Self::NORMAL
+    };
+
+    const fn boss_damage(&self, damage: u64) -> u64 {
+        damage * self.boss_damage_percent / 100
+    }
+
+    /// Extra cost of a spell cast in `round`, counting from 1.
+    const fn surcharge(&self, round: i64) -> u64 {
+        match self.surcharge {
+            Some(surcharge) if round > surcharge.after_rounds => surcharge.extra_cost,
+            _ => 0,
+        }
+    }
+
+    const fn depends_on_round(&self) -> bool {
+        self.surcharge.is_some() || self.max_rounds.is_some()
+    }
+}
+
+impl Default for Modifiers {
+    fn default() -> Self {
+        Self::NORMAL
+    }
 }
 
+/// Dijkstra search over game positions, ordered by mana spent.
+///
+/// A position is only expanded if no position expanded before it dominates it. Since
+/// those were reached for no more mana, anything reachable from the new position is
+/// reachable from the old one at least as cheaply.
 #[derive(Debug, Clone)]
-struct Simulator {
-    visited: HashSet<State>,
-    pending: BinaryHeap<State>,
+struct Simulator<'a> {
+    spells: &'a [Spell],
+    /// Non-dominated expanded states, grouped by their effect timers.
+    expanded: HashMap<Vec<u64>, Vec<State>>,
+    /// The mana spent by each queued state, and its index in `queued`.
+    pending: BinaryHeap<Reverse<(u64, usize)>>,
+    /// Queued states, taken out when they are popped.
+    queued: Vec<Option<State>>,
     min_mana_spent: u64,
+    stats: SearchStats,
 }
 
-impl Simulator {
-    fn new(player: Player, boss: Boss, hard_mode: bool) -> Self {
-        let start_state = State {
-            player,
-            boss,
-            hard_mode,
-            ..Default::default()
-        };
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SearchStats {
+    /// States added to the queue.
+    generated: usize,
+    /// States whose moves were explored.
+    expanded: usize,
+    /// States discarded because an expanded state dominated them.
+    pruned: usize,
+}
+
+impl Display for SearchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} generated, {} expanded, {} pruned",
+            self.generated, self.expanded, self.pruned
+        )
+    }
+}
+
+impl<'a> Simulator<'a> {
+    fn new(player: Player, boss: Boss, modifiers: Modifiers, spells: &'a [Spell]) -> Self {
+        Self::from_state(State::new(player, boss, modifiers, spells), spells)
+    }
 
+    fn from_state(start_state: State, spells: &'a [Spell]) -> Self {
         Self {
-            visited: HashSet::new(),
-            pending: [start_state].into(),
+            spells,
+            expanded: HashMap::new(),
+            pending: [Reverse((start_state.mana_spent, 0))].into(),
+            queued: vec![Some(start_state)],
             min_mana_spent: u64::MAX,
+            stats: SearchStats {
+                generated: 1,
+                ..SearchStats::default()
+            },
         }
     }
+
+    /// The first winning state found is the cheapest one. Its `spells` are the
+    /// winning sequence.
+    fn cheapest_win(&mut self) -> Option<State> {
+        self.next()
+    }
+
+    const fn stats(&self) -> SearchStats {
+        self.stats
+    }
+
+    fn push(&mut self, state: State) {
+        self.pending
+            .push(Reverse((state.mana_spent, self.queued.len())));
+        self.queued.push(Some(state));
+    }
+
+    fn is_dominated(&self, state: &State) -> bool {
+        self.expanded
+            .get(&state.effect_timers)
+            .is_some_and(|group| group.iter().any(|other| other.dominates(state)))
+    }
+
+    fn mark_expanded(&mut self, state: &State) {
+        let group = self
+            .expanded
+            .entry(state.effect_timers.clone())
+            .or_default();
+        group.retain(|other| !state.dominates(other));
+        group.push(state.clone());
+        self.stats.expanded += 1;
+    }
 }
 
-impl Iterator for Simulator {
+impl Iterator for Simulator<'_> {
     type Item = State;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(state) = self.pending.pop() {
+        while let Some(Reverse((_, index))) = self.pending.pop() {
+            let Some(state) = self.queued[index].take() else {
+                continue;
+            };
             if state.mana_spent > self.min_mana_spent {
                 continue;
             }
-            if self.visited.contains(&state) {
+            if self.is_dominated(&state) {
+                self.stats.pruned += 1;
                 continue;
             }
-            self.visited.insert(state.clone());
+            self.mark_expanded(&state);
             if state.boss.is_dead() {
                 self.min_mana_spent = state.mana_spent;
                 return Some(state);
             } else if !state.player.is_dead() {
-                for child in state.moves() {
-                    self.pending.push(child);
+                for child in state.moves(self.spells) {
+                    if self.is_dominated(&child) {
+                        self.stats.pruned += 1;
+                    } else {
+                        self.stats.generated += 1;
+                        self.push(child);
+                    }
                 }
             }
         }
@@ -101,7 +376,7 @@ impl Iterator for Simulator {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 struct Player {
     hp: u64,
     mana: u64,
@@ -124,7 +399,26 @@ impl Player {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+impl FromStr for Player {
+    type Err = ParseError;
+
+    /// Parses e.g. `50 hit points, 500 mana`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut player = Self::new(0, 0);
+        for property in s.split(", ") {
+            let (value, unit) = property.split_once(' ').ok_or(ParseError::SyntaxError)?;
+            let value = value.parse()?;
+            match unit {
+                "hit points" => player.hp = value,
+                "mana" => player.mana = value,
+                _ => return Err(ParseError::UnknownProperty(property.to_string())),
+            }
+        }
+        Ok(player)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 struct Boss {
     hp: u64,
     damage: u64,
@@ -142,7 +436,7 @@ impl Boss {
     }
 }
 
-#[derive(Debug, Default, Eq, Clone)]
+#[derive(Debug, Default, Clone)]
 struct State {
     round: i64,
     player: Player,
@@ -150,55 +444,245 @@ struct State {
 
     mana_spent: u64,
 
-    hard_mode: bool,
+    modifiers: Modifiers,
 
-    effect_timers: [u64; Effect::all().len()],
+    /// Remaining turns of each spell's effect, indexed like the spell book.
+    effect_timers: Vec<u64>,
+
+    /// Indices of the spells cast to reach this state. Not part of the state's identity.
+    spells: Vec<usize>,
 }
 
 impl State {
+    fn new(player: Player, boss: Boss, modifiers: Modifiers, spells: &[Spell]) -> Self {
+        Self {
+            player,
+            boss,
+            modifiers,
+            effect_timers: vec![0; spells.len()],
+            ..Default::default()
+        }
+    }
+
+    /// Whether every battle outcome possible from `other` is possible from `self` too.
+    /// The armor is determined by the effect timers, so it does not need comparing.
+    /// Being at an earlier round only matters if the modifiers depend on it.
+    fn dominates(&self, other: &Self) -> bool {
+        self.player.hp >= other.player.hp
+            && self.player.mana >= other.player.mana
+            && self.boss.hp <= other.boss.hp
+            && self.modifiers == other.modifiers
+            && self.effect_timers == other.effect_timers
+            && (!self.modifiers.depends_on_round() || self.round <= other.round)
+    }
+
     const fn check_win(&self) -> bool {
         self.player.is_dead() || self.boss.is_dead()
     }
 
-    const fn create_child(&self) -> Self {
+    fn create_child(&self) -> Self {
         Self {
             round: self.round + 1,
-            ..*self
+            ..self.clone()
         }
     }
 
-    fn make_move(&self, spell: Spell) -> Option<Self> {
-        let mut child = self.create_child();
+    fn make_move(&self, spell: usize, spells: &[Spell]) -> Option<Self> {
+        self.play_round(spell, spells, &mut |_| {})
+    }
 
-        if child.hard_mode {
-            child.player.take_damage(1);
+    /// Plays the player's turn casting `spells[spell]`, followed by the boss's turn,
+    /// reporting everything that happens to `log`. Returns `None` if the spell cannot
+    /// be cast.
+    fn play_round<'a>(
+        &self,
+        spell: usize,
+        spells: &'a [Spell],
+        log: &mut impl FnMut(Event<'a>),
+    ) -> Option<Self> {
+        if self.is_out_of_rounds() {
+            return None;
         }
+        let mut child = self.create_child();
 
-        for effect in Effect::all() {
-            effect.apply(&mut child);
+        if child.begin_player_turn(spells, log) {
+            return Some(child);
         }
+        child.finish_round(spell, spells, log)
+    }
 
-        if !spell.apply(&mut child) {
+    /// Continues a round after [`Self::begin_player_turn`], casting `spells[spell]` and
+    /// playing the boss's turn. Returns `None` if the spell cannot be cast.
+    fn finish_round<'a>(
+        mut self,
+        spell: usize,
+        spells: &'a [Spell],
+        log: &mut impl FnMut(Event<'a>),
+    ) -> Option<Self> {
+        if !self.cast(spell, spells) {
             return None;
         }
+        log(Event::Cast(&spells[spell]));
+        if self.is_over(log) {
+            return Some(self);
+        }
+
+        log(Event::BossTurn {
+            player: self.player,
+            boss: self.boss,
+        });
+        self.apply_effects(spells, log);
+        if self.is_over(log) {
+            return Some(self);
+        }
+
+        let damage = self.modifiers.boss_damage(self.boss.damage);
+        let armor = self.player.armor;
+        self.player.take_damage(damage);
+        log(Event::BossAttacks { damage, armor });
+        self.is_over(log);
+
+        Some(self)
+    }
+
+    /// Applies everything that happens before the player gets to cast a spell.
+    /// Returns `true` if this ends the battle.
+    fn begin_player_turn<'a>(
+        &mut self,
+        spells: &'a [Spell],
+        log: &mut impl FnMut(Event<'a>),
+    ) -> bool {
+        log(Event::PlayerTurn {
+            player: self.player,
+            boss: self.boss,
+        });
+        let drain = self.modifiers.hp_drain;
+        if drain > 0 {
+            self.player.hp = self.player.hp.saturating_sub(drain);
+            log(Event::Drain(drain));
+            if self.is_over(log) {
+                return true;
+            }
+        }
+        let regen = self.modifiers.mana_regen;
+        if regen > 0 {
+            self.player.mana += regen;
+            log(Event::ManaRegen(regen));
+        }
+        self.apply_effects(spells, log);
+        self.is_over(log)
+    }
+
+    /// The cost of casting `spell` in the current round.
+    const fn spell_cost(&self, spell: &Spell) -> u64 {
+        spell.cost + self.modifiers.surcharge(self.round)
+    }
+
+    const fn is_out_of_rounds(&self) -> bool {
+        matches!(self.modifiers.max_rounds, Some(max_rounds) if self.round >= max_rounds)
+    }
+
+    /// Whether `spells[index]` is affordable and its effect is not already active.
+    fn can_cast(&self, index: usize, spells: &[Spell]) -> bool {
+        self.spell_cost(&spells[index]) <= self.player.mana && self.effect_timers[index] == 0
+    }
+
+    /// Casts `spells[index]`, paying for it. Spells without a duration take effect
+    /// immediately; others start their effect, and add their armor right away. Returns `false` if the spell is not
+    /// affordable or its effect is already active.
+    fn cast(&mut self, index: usize, spells: &[Spell]) -> bool {
+        if !self.can_cast(index, spells) {
+            return false;
+        }
+        let spell = &spells[index];
+        let cost = self.spell_cost(spell);
+        if spell.duration == 0 {
+            self.boss.take_damage(spell.damage);
+            self.player.heal(spell.healing);
+            self.player.mana += spell.recharge;
+        } else {
+            self.effect_timers[index] = spell.duration;
+            self.player.armor += spell.armor;
+        }
+        self.player.mana -= cost;
+        self.mana_spent += cost;
+        self.spells.push(index);
+        true
+    }
 
-        for effect in Effect::all() {
-            effect.apply(&mut child);
+    /// Ticks every active effect. The armor added when an effect started is removed
+    /// when it wears off.
+    fn apply_effects<'a>(&mut self, spells: &'a [Spell], log: &mut impl FnMut(Event<'a>)) {
+        for (spell, timer) in spells.iter().zip(&mut self.effect_timers) {
+            if *timer == 0 {
+                continue;
+            }
+            *timer -= 1;
+            self.boss.take_damage(spell.damage);
+            self.player.heal(spell.healing);
+            self.player.mana += spell.recharge;
+            log(Event::EffectTick {
+                spell,
+                timer: *timer,
+            });
+            if *timer == 0 {
+                self.player.armor -= spell.armor;
+                log(Event::EffectEnds(spell));
+            }
         }
+    }
 
-        let damage = child.boss.damage;
-        child.player.take_damage(damage);
+    fn is_over<'a>(&self, log: &mut impl FnMut(Event<'a>)) -> bool {
+        if self.boss.is_dead() {
+            log(Event::BossDies);
+        } else if self.player.is_dead() {
+            log(Event::PlayerDies);
+        }
+        self.check_win()
+    }
 
-        Some(child)
+    /// Replays the spells with the given indices from this state, recording every turn.
+    /// If the battle is decided by effects at the start of the following turn, that
+    /// turn is included.
+    fn replay<'a>(
+        &self,
+        cast: &[usize],
+        spells: &'a [Spell],
+    ) -> Result<Vec<Event<'a>>, ReplayError> {
+        let mut events = Vec::new();
+        let mut state = self.clone();
+        for (round, &spell) in cast.iter().enumerate() {
+            if state.check_win() {
+                return Err(ReplayError::BattleOver { round });
+            }
+            if state.is_out_of_rounds() {
+                return Err(ReplayError::OutOfRounds { round });
+            }
+            state = state
+                .play_round(spell, spells, &mut |event| events.push(event))
+                .ok_or_else(|| ReplayError::CannotCast {
+                    round,
+                    spell: spells[spell].name.clone(),
+                })?;
+        }
+        if !state.check_win() {
+            let mut next = state.create_child();
+            let mut tail = Vec::new();
+            if next.begin_player_turn(spells, &mut |event| tail.push(event)) && next.boss.is_dead()
+            {
+                events.extend(tail);
+            }
+        }
+        Ok(events)
     }
 
-    fn moves(&self) -> Vec<Self> {
+    fn moves(&self, spells: &[Spell]) -> Vec<Self> {
         let mut res = Vec::new();
         if self.check_win() {
             return res;
         }
-        for spell in Spell::all() {
-            if let Some(child) = self.make_move(spell) {
+        for spell in 0..spells.len() {
+            if let Some(child) = self.make_move(spell, spells) {
                 res.push(child);
             }
         }
@@ -206,128 +690,296 @@ impl State {
     }
 }
 
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        other.mana_spent.cmp(&self.mana_spent)
-    }
+/// Something that happens during a battle, displayed in the puzzle's narrative format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event<'a> {
+    PlayerTurn { player: Player, boss: Boss },
+    BossTurn { player: Player, boss: Boss },
+    Drain(u64),
+    ManaRegen(u64),
+    EffectTick { spell: &'a Spell, timer: u64 },
+    EffectEnds(&'a Spell),
+    Cast(&'a Spell),
+    BossAttacks { damage: u64, armor: u64 },
+    BossDies,
+    PlayerDies,
 }
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+
+impl Event<'_> {
+    /// Whether the event continues the line of the previous event.
+    const fn is_conclusion(&self) -> bool {
+        matches!(self, Self::BossDies | Self::PlayerDies)
     }
 }
 
-impl PartialEq for State {
-    fn eq(&self, other: &Self) -> bool {
-        self.round == other.round
-            && self.player == other.player
-            && self.boss == other.boss
-            && self.mana_spent == other.mana_spent
-            && self.hard_mode == other.hard_mode
-            && self.effect_timers == other.effect_timers
+const fn plural(count: u64) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+impl Display for Event<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::PlayerTurn { player, boss } | Self::BossTurn { player, boss } => {
+                let side = if matches!(self, Self::PlayerTurn { .. }) {
+                    "Player"
+                } else {
+                    "Boss"
+                };
+                writeln!(f, "-- {side} turn --")?;
+                writeln!(
+                    f,
+                    "- Player has {} hit point{}, {} armor, {} mana",
+                    player.hp,
+                    plural(player.hp),
+                    player.armor,
+                    player.mana
+                )?;
+                write!(f, "- Boss has {} hit point{}", boss.hp, plural(boss.hp))
+            }
+            Self::Drain(hp) => write!(f, "Player loses {hp} hit point{}.", plural(hp)),
+            Self::ManaRegen(mana) => write!(f, "Player regenerates {mana} mana."),
+            Self::EffectTick { spell, timer } => {
+                let mut effects = Vec::new();
+                if spell.damage > 0 {
+                    effects.push(format!("deals {} damage", spell.damage));
+                }
+                if spell.healing > 0 {
+                    let healing = spell.healing;
+                    effects.push(format!("heals {healing} hit point{}", plural(healing)));
+                }
+                if spell.recharge > 0 {
+                    effects.push(format!("provides {} mana", spell.recharge));
+                }
+                if effects.is_empty() {
+                    write!(f, "{}'s timer is now {timer}.", spell.name)
+                } else {
+                    let effects = effects.join(" and ");
+                    write!(f, "{} {effects}; its timer is now {timer}.", spell.name)
+                }
+            }
+            Self::EffectEnds(spell) if spell.armor > 0 => write!(
+                f,
+                "{} wears off, decreasing armor by {}.",
+                spell.name, spell.armor
+            ),
+            Self::EffectEnds(spell) => write!(f, "{} wears off.", spell.name),
+            Self::Cast(spell) => {
+                write!(f, "Player casts {}", spell.name)?;
+                if spell.duration == 0 {
+                    if spell.damage > 0 {
+                        write!(f, ", dealing {} damage", spell.damage)?;
+                    }
+                    if spell.healing > 0 {
+                        let healing = spell.healing;
+                        write!(f, ", and healing {healing} hit point{}", plural(healing))?;
+                    }
+                    if spell.recharge > 0 {
+                        write!(f, ", and gaining {} mana", spell.recharge)?;
+                    }
+                } else if spell.armor > 0 {
+                    write!(f, ", increasing armor by {}", spell.armor)?;
+                }
+                write!(f, ".")
+            }
+            Self::BossAttacks { damage, armor: 0 } => {
+                write!(f, "Boss attacks for {damage} damage.")
+            }
+            Self::BossAttacks { damage, armor } => write!(
+                f,
+                "Boss attacks for {damage} - {armor} = {} damage!",
+                damage.saturating_sub(armor).max(1)
+            ),
+            Self::BossDies => write!(f, "This kills the boss, and the player wins."),
+            Self::PlayerDies => write!(f, "This kills the player, and the boss wins."),
+        }
     }
 }
 
-impl Hash for State {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.round.hash(state);
-        self.player.hash(state);
-        self.boss.hash(state);
-        self.mana_spent.hash(state);
-        self.hard_mode.hash(state);
-        self.effect_timers.hash(state);
+/// Formats a battle log the way the puzzle narrates it.
+fn narrate(events: &[Event]) -> String {
+    let mut text = String::new();
+    for event in events {
+        if matches!(event, Event::PlayerTurn { .. } | Event::BossTurn { .. }) && !text.is_empty() {
+            text.push_str("\n\n");
+        } else if event.is_conclusion() {
+            text.push(' ');
+        } else if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&event.to_string());
     }
+    text
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Spell {
-    MagicMissile,
-    Drain,
-    Effect(Effect),
+/// A spell book entry. Spells without a duration take effect once when cast; others
+/// apply their damage, healing and recharge at the start of each turn while active,
+/// and add their armor for as long as they last.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Spell {
+    name: String,
+    cost: u64,
+    damage: u64,
+    healing: u64,
+    armor: u64,
+    recharge: u64,
+    duration: u64,
 }
 
-impl Spell {
-    const fn all() -> [Self; 5] {
-        [
-            Self::MagicMissile,
-            Self::Drain,
-            Self::Effect(Effect::Shield),
-            Self::Effect(Effect::Poison),
-            Self::Effect(Effect::Recharge),
-        ]
-    }
+impl FromStr for Spell {
+    type Err = ParseError;
 
-    const fn mana_cost(self) -> u64 {
-        match self {
-            Self::MagicMissile => 53,
-            Self::Drain => 73,
-            Self::Effect(Effect::Shield) => 113,
-            Self::Effect(Effect::Poison) => 173,
-            Self::Effect(Effect::Recharge) => 229,
+    /// Parses e.g. `costs 229, 101 mana, lasts 5`. The name is left empty.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spell = Self::default();
+        for property in s.split(", ") {
+            let (first, second) = property.split_once(' ').ok_or(ParseError::SyntaxError)?;
+            match (first, second) {
+                ("costs", value) => spell.cost = value.parse()?,
+                ("lasts", value) => spell.duration = value.parse()?,
+                (value, "damage") => spell.damage = value.parse()?,
+                (value, "healing") => spell.healing = value.parse()?,
+                (value, "armor") => spell.armor = value.parse()?,
+                (value, "mana") => spell.recharge = value.parse()?,
+                _ => return Err(ParseError::UnknownProperty(property.to_string())),
+            }
         }
+        if spell.armor > 0 && spell.duration == 0 {
+            return Err(ParseError::ArmorWithoutDuration);
+        }
+        Ok(spell)
     }
+}
 
-    const fn apply(self, state: &mut State) -> bool {
-        let cost = self.mana_cost();
-        if cost > state.player.mana {
-            return false;
+/// Plays the battle against the boss in `puzzle_input` interactively.
+///
+/// Spell choices are read from `input`, and the battle is narrated to `output`. With
+/// `hints`, the first spell of the cheapest win from the current position is suggested
+/// every turn.
+///
+/// # Errors
+///
+/// Returns an error if the puzzle input is invalid, or if reading or writing fails.
+pub fn play(
+    puzzle_input: &str,
+    hard_mode: bool,
+    hints: bool,
+    input: impl BufRead,
+    output: impl Write,
+) -> io::Result<()> {
+    let battle = parse(puzzle_input).map_err(io::Error::other)?;
+    let modifiers = if hard_mode {
+        Modifiers::HARD
+    } else {
+        Modifiers::NORMAL
+    };
+    let Config { player, spells } = &battle.config;
+    let start = State::new(*player, battle.boss, modifiers, spells);
+    play_battle(start, spells, hints, input, output)
+}
+
+fn play_battle(
+    mut state: State,
+    spells: &[Spell],
+    hints: bool,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    loop {
+        if state.is_out_of_rounds() {
+            writeln!(output, "The round limit is reached, and the boss wins.")?;
+            return Ok(());
+        }
+        let mut turn = state.create_child();
+        let mut events = Vec::new();
+        let over = turn.begin_player_turn(spells, &mut |event| events.push(event));
+        writeln!(output, "{}", narrate(&events))?;
+        if over {
+            return Ok(());
         }
-        match self {
-            Self::MagicMissile => {
-                state.boss.take_damage(4);
+        if !(0..spells.len()).any(|index| turn.can_cast(index, spells)) {
+            writeln!(
+                output,
+                "The player cannot afford to cast any spell, and the boss wins."
+            )?;
+            return Ok(());
+        }
+
+        write_spell_menu(&mut output, &turn, spells)?;
+        if hints {
+            let hint = Simulator::from_state(state.clone(), spells)
+                .cheapest_win()
+                .and_then(|win| win.spells.get(state.spells.len()).copied());
+            match hint {
+                Some(index) => writeln!(output, "Hint: cast {}.", spells[index].name)?,
+                None => writeln!(output, "Hint: the boss cannot be beaten from here.")?,
             }
-            Self::Drain => {
-                state.boss.take_damage(2);
-                state.player.heal(2);
+        }
+
+        state = loop {
+            write!(output, "Cast which spell? ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(output)?;
+                return Ok(());
             }
-            Self::Effect(eff) => {
-                if state.effect_timers[eff.index()] > 0 {
-                    return false;
-                }
-                state.effect_timers[eff.index()] = eff.duration();
+            let Some(index) = choose_spell(line.trim(), spells) else {
+                writeln!(output, "Unknown spell: {}", line.trim())?;
+                continue;
+            };
+            let mut events = Vec::new();
+            if let Some(next) = turn
+                .clone()
+                .finish_round(index, spells, &mut |event| events.push(event))
+            {
+                writeln!(output, "{}\n", narrate(&events))?;
+                break next;
             }
+            writeln!(output, "You cannot cast {} now.", spells[index].name)?;
+        };
+        if state.check_win() {
+            return Ok(());
         }
-        state.player.mana -= cost;
-        state.mana_spent += cost;
-        true
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-enum Effect {
-    Shield,
-    Poison,
-    Recharge,
-}
-
-impl Effect {
-    const fn all() -> [Self; 3] {
-        [Self::Shield, Self::Poison, Self::Recharge]
+fn write_spell_menu(output: &mut impl Write, turn: &State, spells: &[Spell]) -> io::Result<()> {
+    let active = spells
+        .iter()
+        .zip(&turn.effect_timers)
+        .filter(|&(_, &timer)| timer > 0)
+        .map(|(spell, timer)| format!("{} ({timer})", spell.name))
+        .collect::<Vec<_>>();
+    if !active.is_empty() {
+        writeln!(output, "Active effects: {}", active.join(", "))?;
     }
-    const fn index(self) -> usize {
-        self as usize
-    }
-    const fn duration(self) -> u64 {
-        match self {
-            Self::Shield | Self::Poison => 6,
-            Self::Recharge => 5,
-        }
+    for (index, spell) in spells.iter().enumerate() {
+        let cost = turn.spell_cost(spell);
+        let note = if turn.effect_timers[index] > 0 {
+            " (active)"
+        } else if cost > turn.player.mana {
+            " (not enough mana)"
+        } else {
+            ""
+        };
+        writeln!(
+            output,
+            "  {}) {} - {cost} mana{note}",
+            index + 1,
+            spell.name
+        )?;
     }
+    Ok(())
+}
 
-    fn apply(self, state: &mut State) {
-        let index = self.index();
-        if state.effect_timers[index] > 0 {
-            state.effect_timers[index] -= 1;
-            match self {
-                Self::Shield => state.player.armor = 7,
-                Self::Poison => state.boss.hp = state.boss.hp.saturating_sub(3),
-                Self::Recharge => state.player.mana += 101,
-            }
-        } else if self == Self::Shield {
-            state.player.armor = 0;
-        }
+/// Finds a spell by its number in the menu, or by its name ignoring case.
+fn choose_spell(choice: &str, spells: &[Spell]) -> Option<usize> {
+    if let Ok(number) = choice.parse::<usize>() {
+        return (1..=spells.len()).contains(&number).then(|| number - 1);
     }
+    spells
+        .iter()
+        .position(|spell| spell.name.eq_ignore_ascii_case(choice))
 }
 
 #[cfg(test)]
@@ -335,15 +987,368 @@ mod tests {
     use super::*;
     use test_case::test_case;
 
+    const MAGIC_MISSILE: usize = 0;
+    const DRAIN: usize = 1;
+    const SHIELD: usize = 2;
+    const POISON: usize = 3;
+    const RECHARGE: usize = 4;
+
+    /// The start of the puzzle's examples: a player with 10 hit points against a boss dealing 8 damage.
+    fn example_start(mana: u64, boss_hp: u64, spells: &[Spell]) -> State {
+        State::new(
+            Player::new(10, mana),
+            Boss::new(boss_hp, 8),
+            Modifiers::NORMAL,
+            spells,
+        )
+    }
+
     #[test_case(13 => 226)]
     #[test_case(14 => 641)]
     fn test_part_1(boss_hp: u64) -> u64 {
         let boss = Boss::new(boss_hp, 8);
         let player = Player::new(10, 250);
+        let spells = Config::standard().spells;
 
-        Simulator::new(player, boss, false)
+        Simulator::new(player, boss, Modifiers::NORMAL, &spells)
             .map(|s| s.mana_spent)
             .min()
             .unwrap()
     }
+
+    #[test_case(13 => vec!["Poison", "Magic Missile"])]
+    #[test_case(14 => vec!["Recharge", "Shield", "Drain", "Poison", "Magic Missile"])]
+    fn test_cheapest_win(boss_hp: u64) -> Vec<String> {
+        let boss = Boss::new(boss_hp, 8);
+        let player = Player::new(10, 250);
+        let spells = Config::standard().spells;
+
+        Simulator::new(player, boss, Modifiers::NORMAL, &spells)
+            .cheapest_win()
+            .unwrap()
+            .spells
+            .into_iter()
+            .map(|index| spells[index].name.clone())
+            .collect()
+    }
+
+    /// Counts the states a plain Dijkstra search expands before the first win, without
+    /// pruning dominated states.
+    fn expansions_without_pruning(start: State, spells: &[Spell]) -> usize {
+        let mut queued = vec![Some(start)];
+        let mut pending = BinaryHeap::from([Reverse((0, 0))]);
+        let mut expanded = 0;
+        while let Some(Reverse((_, index))) = pending.pop() {
+            let state = queued[index].take().unwrap();
+            expanded += 1;
+            if state.boss.is_dead() {
+                break;
+            }
+            for child in state.moves(spells) {
+                pending.push(Reverse((child.mana_spent, queued.len())));
+                queued.push(Some(child));
+            }
+        }
+        expanded
+    }
+
+    #[test_case(Modifiers::NORMAL => 1269)]
+    #[test_case(Modifiers::HARD => 1309)]
+    fn test_search_stats(modifiers: Modifiers) -> u64 {
+        let config = Config::standard();
+        let start = State::new(config.player, Boss::new(58, 9), modifiers, &config.spells);
+        let mut simulator = Simulator::from_state(start.clone(), &config.spells);
+        let mana_spent = simulator.cheapest_win().map_or(0, |win| win.mana_spent);
+        let stats = simulator.stats();
+        let baseline = expansions_without_pruning(start, &config.spells);
+        assert!(stats.pruned > 0);
+        assert!(stats.expanded < baseline);
+        mana_spent
+    }
+
+    #[test_case(Modifiers::NORMAL => Some(226); "normal")]
+    #[test_case(Modifiers { max_rounds: Some(1), ..Modifiers::NORMAL } => None; "one round")]
+    #[test_case(Modifiers { max_rounds: Some(2), ..Modifiers::NORMAL } => Some(226); "two rounds")]
+    #[test_case(Modifiers {
+        surcharge: Some(Surcharge { after_rounds: 1, extra_cost: 10 }),
+        ..Modifiers::NORMAL
+    } => Some(236); "surcharge")]
+    #[test_case(Modifiers::HARD => None; "hard")]
+    #[test_case(Modifiers { boss_damage_percent: 200, ..Modifiers::NORMAL } => None; "double damage")]
+    #[test_case(Modifiers { boss_damage_percent: 50, ..Modifiers::HARD } => Some(226); "half damage")]
+    #[test_case(Modifiers { mana_regen: 100, ..Modifiers::HARD } => Some(339); "mana regen")]
+    fn test_modifiers(modifiers: Modifiers) -> Option<u64> {
+        let spells = Config::standard().spells;
+        Simulator::new(Player::new(10, 250), Boss::new(13, 8), modifiers, &spells)
+            .cheapest_win()
+            .map(|win| win.mana_spent)
+    }
+
+    #[test]
+    fn test_play() {
+        let spells = Config::standard().spells;
+        let start = example_start(250, 13, &spells);
+        let mut output = Vec::new();
+        play_battle(
+            start,
+            &spells,
+            true,
+            &b"shield\npoison\n2\n"[..],
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("  4) Poison - 173 mana\n"));
+        assert!(output.contains("Hint: cast Poison."));
+        assert!(output.contains("Player casts Shield, increasing armor by 7."));
+        assert!(output.contains("Active effects: Shield (4)"));
+        assert!(output.contains("Hint: the boss cannot be beaten from here."));
+        assert!(output.contains("  2) Drain - 73 mana (not enough mana)\n"));
+        assert!(output.contains("You cannot cast Poison now."));
+        assert!(output.contains("Player casts Drain, dealing 2 damage, and healing 2 hit points."));
+        assert!(output.ends_with("Cast which spell? \n"));
+    }
+
+    #[test]
+    fn test_play_win() {
+        let spells = Config::standard().spells;
+        let start = example_start(250, 13, &spells);
+        let mut output = Vec::new();
+        play_battle(
+            start,
+            &spells,
+            false,
+            &b"4\nMagic Missile\n"[..],
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(!output.contains("Hint"));
+        assert!(output.ends_with("This kills the boss, and the player wins.\n\n"));
+    }
+
+    #[test]
+    fn test_play_cannot_cast() {
+        let spells = Config::standard().spells;
+        let start = example_start(10, 13, &spells);
+        let mut output = Vec::new();
+        play_battle(start, &spells, false, &b"1\n"[..], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(!output.contains("Cast which spell?"));
+        assert!(
+            output.ends_with("The player cannot afford to cast any spell, and the boss wins.\n")
+        );
+    }
+
+    #[test_case("3" => Some(2))]
+    #[test_case("magic missile" => Some(0))]
+    #[test_case("6" => None)]
+    #[test_case("0" => None)]
+    #[test_case("Fireball" => None)]
+    fn test_choose_spell(choice: &str) -> Option<usize> {
+        choose_spell(choice, &Config::standard().spells)
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::standard();
+        assert_eq!(config.player, Player::new(50, 500));
+        assert_eq!(config.spells.len(), 5);
+        assert_eq!(
+            config.spells[4],
+            Spell {
+                name: "Recharge".to_string(),
+                cost: 229,
+                recharge: 101,
+                duration: 5,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_with_config() {
+        let battle = parse(
+            "\
+            Hit Points: 13\n\
+            Damage: 8\n\
+            \n\
+            Player: 10 hit points, 250 mana\n\
+            Fireball: costs 100, 13 damage\n\
+            Regenerate: costs 10, 1 healing, lasts 10\
+            ",
+        )
+        .unwrap();
+        assert_eq!(battle.boss, Boss::new(13, 8));
+        assert_eq!(battle.config.player, Player::new(10, 250));
+        assert_eq!(battle.config.spells.len(), 2);
+        assert_eq!(part_1(&battle), Ok(100));
+    }
+
+    #[test]
+    fn test_no_win() {
+        let battle = parse(
+            "\
+            Hit Points: 100\n\
+            Damage: 8\n\
+            \n\
+            Player: 10 hit points, 250 mana\n\
+            Zap: costs 100, 1 damage\
+            ",
+        )
+        .unwrap();
+        assert_eq!(part_1(&battle), Err(ReplayError::NoWin));
+        assert_eq!(part_2(&battle), Err(ReplayError::NoWin));
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        assert!(matches!(
+            "Zap: costs 1, 1 damage".parse::<Config>(),
+            Err(ParseError::MissingPlayer)
+        ));
+        assert!(matches!(
+            "Player: 1 hit points, 1 mana".parse::<Config>(),
+            Err(ParseError::NoSpells)
+        ));
+        assert!(matches!(
+            "Player: 1 hit points\nZap: 1 fire".parse::<Config>(),
+            Err(ParseError::UnknownProperty(_))
+        ));
+        assert!(matches!(
+            "Player: 1 hit points\nPlayer: 2 hit points\nZap: costs 1, 1 damage".parse::<Config>(),
+            Err(ParseError::DuplicatePlayer)
+        ));
+        assert!(matches!(
+            "Player: 1 hit points\nWall: costs 1, 7 armor".parse::<Config>(),
+            Err(ParseError::ArmorWithoutDuration)
+        ));
+    }
+
+    #[test]
+    fn test_replay() {
+        let spells = Config::standard().spells;
+        let start = example_start(250, 13, &spells);
+        let events = start.replay(&[POISON, MAGIC_MISSILE], &spells).unwrap();
+        assert_eq!(
+            narrate(&events),
+            "\
+            -- Player turn --\n\
+            - Player has 10 hit points, 0 armor, 250 mana\n\
+            - Boss has 13 hit points\n\
+            Player casts Poison.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 10 hit points, 0 armor, 77 mana\n\
+            - Boss has 13 hit points\n\
+            Poison deals 3 damage; its timer is now 5.\n\
+            Boss attacks for 8 damage.\n\
+            \n\
+            -- Player turn --\n\
+            - Player has 2 hit points, 0 armor, 77 mana\n\
+            - Boss has 10 hit points\n\
+            Poison deals 3 damage; its timer is now 4.\n\
+            Player casts Magic Missile, dealing 4 damage.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 2 hit points, 0 armor, 24 mana\n\
+            - Boss has 3 hit points\n\
+            Poison deals 3 damage; its timer is now 3. This kills the boss, and the player wins.\
+            "
+        );
+    }
+
+    #[test]
+    fn test_replay_second_example() {
+        let spells = Config::standard().spells;
+        let start = example_start(250, 14, &spells);
+        let events = start
+            .replay(&[RECHARGE, SHIELD, DRAIN, POISON, MAGIC_MISSILE], &spells)
+            .unwrap();
+        assert_eq!(
+            narrate(&events),
+            "\
+            -- Player turn --\n\
+            - Player has 10 hit points, 0 armor, 250 mana\n\
+            - Boss has 14 hit points\n\
+            Player casts Recharge.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 10 hit points, 0 armor, 21 mana\n\
+            - Boss has 14 hit points\n\
+            Recharge provides 101 mana; its timer is now 4.\n\
+            Boss attacks for 8 damage.\n\
+            \n\
+            -- Player turn --\n\
+            - Player has 2 hit points, 0 armor, 122 mana\n\
+            - Boss has 14 hit points\n\
+            Recharge provides 101 mana; its timer is now 3.\n\
+            Player casts Shield, increasing armor by 7.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 2 hit points, 7 armor, 110 mana\n\
+            - Boss has 14 hit points\n\
+            Shield's timer is now 5.\n\
+            Recharge provides 101 mana; its timer is now 2.\n\
+            Boss attacks for 8 - 7 = 1 damage!\n\
+            \n\
+            -- Player turn --\n\
+            - Player has 1 hit point, 7 armor, 211 mana\n\
+            - Boss has 14 hit points\n\
+            Shield's timer is now 4.\n\
+            Recharge provides 101 mana; its timer is now 1.\n\
+            Player casts Drain, dealing 2 damage, and healing 2 hit points.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 3 hit points, 7 armor, 239 mana\n\
+            - Boss has 12 hit points\n\
+            Shield's timer is now 3.\n\
+            Recharge provides 101 mana; its timer is now 0.\n\
+            Recharge wears off.\n\
+            Boss attacks for 8 - 7 = 1 damage!\n\
+            \n\
+            -- Player turn --\n\
+            - Player has 2 hit points, 7 armor, 340 mana\n\
+            - Boss has 12 hit points\n\
+            Shield's timer is now 2.\n\
+            Player casts Poison.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 2 hit points, 7 armor, 167 mana\n\
+            - Boss has 12 hit points\n\
+            Shield's timer is now 1.\n\
+            Poison deals 3 damage; its timer is now 5.\n\
+            Boss attacks for 8 - 7 = 1 damage!\n\
+            \n\
+            -- Player turn --\n\
+            - Player has 1 hit point, 7 armor, 167 mana\n\
+            - Boss has 9 hit points\n\
+            Shield's timer is now 0.\n\
+            Shield wears off, decreasing armor by 7.\n\
+            Poison deals 3 damage; its timer is now 4.\n\
+            Player casts Magic Missile, dealing 4 damage.\n\
+            \n\
+            -- Boss turn --\n\
+            - Player has 1 hit point, 0 armor, 114 mana\n\
+            - Boss has 2 hit points\n\
+            Poison deals 3 damage; its timer is now 3. This kills the boss, and the player wins.\
+            "
+        );
+    }
+
+    #[test]
+    fn test_replay_invalid() {
+        let spells = Config::standard().spells;
+        let start = example_start(250, 13, &spells);
+        assert_eq!(
+            start.replay(&[POISON, POISON], &spells),
+            Err(ReplayError::CannotCast {
+                round: 1,
+                spell: "Poison".to_string()
+            })
+        );
+    }
 }
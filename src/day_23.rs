@@ -1,4 +1,5 @@
-use std::num::ParseIntError;
+use std::fmt::Display;
+use std::num::{ParseIntError, Saturating, Wrapping};
 use std::ops::{Index, IndexMut};
 use std::str::FromStr;
 
@@ -75,50 +76,241 @@ impl FromStr for Op {
     }
 }
 
-struct Machine {
-    registers: [u64; 2],
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Arithmetic overflow at ip {ip}: {op:?}")]
+struct OverflowError {
+    ip: usize,
+    op: Op,
+}
+
+/// Register storage, selecting how the machine handles values that do not fit.
+///
+/// * `u64` reports an [`OverflowError`].
+/// * `Wrapping<u64>` wraps around modulo 2^64.
+/// * `Saturating<u64>` clamps at `u64::MAX`.
+/// * [`BigUint`] grows as needed.
+trait Word: Clone + Default {
+    fn from_u64(value: u64) -> Self;
+    fn half(&mut self);
+    /// Returns `false`, leaving the value unchanged, if the result does not fit.
+    fn triple(&mut self) -> bool;
+    /// Returns `false`, leaving the value unchanged, if the result does not fit.
+    fn increment(&mut self) -> bool;
+    fn is_even(&self) -> bool;
+    fn is_one(&self) -> bool;
+}
+
+impl Word for u64 {
+    fn from_u64(value: u64) -> Self {
+        value
+    }
+    fn half(&mut self) {
+        *self /= 2;
+    }
+    fn triple(&mut self) -> bool {
+        self.checked_mul(3).map(|value| *self = value).is_some()
+    }
+    fn increment(&mut self) -> bool {
+        self.checked_add(1).map(|value| *self = value).is_some()
+    }
+    fn is_even(&self) -> bool {
+        *self & 1 == 0
+    }
+    fn is_one(&self) -> bool {
+        *self == 1
+    }
+}
+
+impl Word for Wrapping<u64> {
+    fn from_u64(value: u64) -> Self {
+        Self(value)
+    }
+    fn half(&mut self) {
+        *self /= 2;
+    }
+    fn triple(&mut self) -> bool {
+        *self *= 3;
+        true
+    }
+    fn increment(&mut self) -> bool {
+        *self += 1;
+        true
+    }
+    fn is_even(&self) -> bool {
+        self.0 & 1 == 0
+    }
+    fn is_one(&self) -> bool {
+        self.0 == 1
+    }
+}
+
+impl Word for Saturating<u64> {
+    fn from_u64(value: u64) -> Self {
+        Self(value)
+    }
+    fn half(&mut self) {
+        *self /= 2;
+    }
+    fn triple(&mut self) -> bool {
+        *self *= 3;
+        true
+    }
+    fn increment(&mut self) -> bool {
+        *self += 1;
+        true
+    }
+    fn is_even(&self) -> bool {
+        self.0 & 1 == 0
+    }
+    fn is_one(&self) -> bool {
+        self.0 == 1
+    }
+}
+
+/// Arbitrary precision unsigned integer, supporting only what the machine needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BigUint {
+    /// Little-endian, without trailing zero limbs.
+    limbs: Vec<u32>,
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    reason = "splitting into 32-bit limbs"
+)]
+impl BigUint {
+    fn mul_add_small(&mut self, factor: u32, addend: u32) {
+        let mut carry = u64::from(addend);
+        for limb in &mut self.limbs {
+            let value = u64::from(*limb) * u64::from(factor) + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+        if carry != 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+
+    /// Divides in place, returning the remainder.
+    fn div_small(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = (remainder << 32) | u64::from(*limb);
+            *limb = (value / u64::from(divisor)) as u32;
+            remainder = value % u64::from(divisor);
+        }
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        remainder as u32
+    }
+}
+
+impl Word for BigUint {
+    #[allow(
+        clippy::cast_possible_truncation,
+        reason = "splitting into 32-bit limbs"
+    )]
+    fn from_u64(value: u64) -> Self {
+        let mut limbs = vec![value as u32, (value >> 32) as u32];
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        Self { limbs }
+    }
+    fn half(&mut self) {
+        self.div_small(2);
+    }
+    fn triple(&mut self) -> bool {
+        self.mul_add_small(3, 0);
+        true
+    }
+    fn increment(&mut self) -> bool {
+        self.mul_add_small(1, 1);
+        true
+    }
+    fn is_even(&self) -> bool {
+        self.limbs.first().is_none_or(|&limb| limb & 1 == 0)
+    }
+    fn is_one(&self) -> bool {
+        self.limbs == [1]
+    }
+}
+
+impl Display for BigUint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const CHUNK: u32 = 1_000_000_000;
+        let mut value = self.clone();
+        let mut chunks = Vec::new();
+        while !value.limbs.is_empty() {
+            chunks.push(value.div_small(CHUNK));
+        }
+        let Some((first, rest)) = chunks.split_last() else {
+            return write!(f, "0");
+        };
+        write!(f, "{first}")?;
+        for chunk in rest.iter().rev() {
+            write!(f, "{chunk:09}")?;
+        }
+        Ok(())
+    }
+}
+
+struct Machine<W = u64> {
+    registers: [W; 2],
     ip: usize,
     instructions: Vec<Op>,
 }
 
-impl Machine {
-    const fn new(instructions: Vec<Op>) -> Self {
+impl<W: Word> Machine<W> {
+    fn new(instructions: Vec<Op>) -> Self {
         Self {
-            registers: [0; 2],
+            registers: Default::default(),
             ip: 0,
             instructions,
         }
     }
 
-    fn run(&mut self) {
+    fn run(&mut self) -> Result<(), OverflowError> {
         while self.ip < self.instructions.len() {
-            self.step();
+            self.step()?;
         }
+        Ok(())
     }
 
-    fn step(&mut self) {
-        match self.instructions[self.ip] {
-            Op::Hlf(reg) => self[reg] /= 2,
-            Op::Tpl(reg) => self[reg] *= 3,
-            Op::Inc(reg) => self[reg] += 1,
+    fn step(&mut self) -> Result<(), OverflowError> {
+        let op = self.instructions[self.ip];
+        let fits = match op {
+            Op::Hlf(reg) => {
+                self[reg].half();
+                true
+            }
+            Op::Tpl(reg) => self[reg].triple(),
+            Op::Inc(reg) => self[reg].increment(),
             Op::Jmp(delta) => {
                 self.try_jump(delta);
-                return;
+                return Ok(());
             }
             Op::Jie(reg, delta) => {
-                if self[reg] & 1 == 0 {
+                if self[reg].is_even() {
                     self.try_jump(delta);
-                    return;
+                    return Ok(());
                 }
+                true
             }
             Op::Jio(reg, delta) => {
-                if self[reg] == 1 {
+                if self[reg].is_one() {
                     self.try_jump(delta);
-                    return;
+                    return Ok(());
                 }
+                true
             }
+        };
+        if !fits {
+            return Err(OverflowError { ip: self.ip, op });
         }
         self.ip += 1;
+        Ok(())
     }
 
     const fn try_jump(&mut self, delta: isize) {
@@ -132,15 +324,15 @@ impl Machine {
     }
 }
 
-impl Index<Reg> for Machine {
-    type Output = u64;
+impl<W> Index<Reg> for Machine<W> {
+    type Output = W;
 
     fn index(&self, reg: Reg) -> &Self::Output {
         &self.registers[reg as usize]
     }
 }
 
-impl IndexMut<Reg> for Machine {
+impl<W> IndexMut<Reg> for Machine<W> {
     fn index_mut(&mut self, reg: Reg) -> &mut Self::Output {
         &mut self.registers[reg as usize]
     }
@@ -152,18 +344,63 @@ fn parse(input: &str) -> Result<Vec<Op>, ParseError> {
 }
 
 #[aoc(day23, part1)]
-fn part_1(ops: &[Op]) -> u64 {
-    let mut machine = Machine::new(ops.to_vec());
-    machine.run();
-    machine[Reg::B]
+fn part_1(ops: &[Op]) -> Result<u64, OverflowError> {
+    let mut machine = Machine::<u64>::new(ops.to_vec());
+    machine.run()?;
+    Ok(machine[Reg::B])
 }
 
 #[aoc(day23, part2)]
-fn part_2(ops: &[Op]) -> u64 {
-    let mut machine = Machine::new(ops.to_vec());
+fn part_2(ops: &[Op]) -> Result<u64, OverflowError> {
+    let mut machine = Machine::<u64>::new(ops.to_vec());
     machine[Reg::A] = 1;
-    machine.run();
-    machine[Reg::B]
+    machine.run()?;
+    Ok(machine[Reg::B])
+}
+
+#[aoc(day23, part1, arbitrary_precision)]
+fn part_1_arbitrary_precision(ops: &[Op]) -> Result<BigUint, OverflowError> {
+    let mut machine = Machine::<BigUint>::new(ops.to_vec());
+    machine.run()?;
+    Ok(machine[Reg::B].clone())
+}
+
+#[aoc(day23, part2, arbitrary_precision)]
+fn part_2_arbitrary_precision(ops: &[Op]) -> Result<BigUint, OverflowError> {
+    let mut machine = Machine::<BigUint>::new(ops.to_vec());
+    machine[Reg::A] = BigUint::from_u64(1);
+    machine.run()?;
+    Ok(machine[Reg::B].clone())
+}
+
+#[aoc(day23, part1, wrapping)]
+fn part_1_wrapping(ops: &[Op]) -> Result<u64, OverflowError> {
+    let mut machine = Machine::<Wrapping<u64>>::new(ops.to_vec());
+    machine.run()?;
+    Ok(machine[Reg::B].0)
+}
+
+#[aoc(day23, part2, wrapping)]
+fn part_2_wrapping(ops: &[Op]) -> Result<u64, OverflowError> {
+    let mut machine = Machine::<Wrapping<u64>>::new(ops.to_vec());
+    machine[Reg::A] = Wrapping(1);
+    machine.run()?;
+    Ok(machine[Reg::B].0)
+}
+
+#[aoc(day23, part1, saturating)]
+fn part_1_saturating(ops: &[Op]) -> Result<u64, OverflowError> {
+    let mut machine = Machine::<Saturating<u64>>::new(ops.to_vec());
+    machine.run()?;
+    Ok(machine[Reg::B].0)
+}
+
+#[aoc(day23, part2, saturating)]
+fn part_2_saturating(ops: &[Op]) -> Result<u64, OverflowError> {
+    let mut machine = Machine::<Saturating<u64>>::new(ops.to_vec());
+    machine[Reg::A] = Saturating(1);
+    machine.run()?;
+    Ok(machine[Reg::B].0)
 }
 
 #[cfg(test)]
@@ -195,8 +432,73 @@ mod tests {
     #[test]
     fn test_part_1() {
         let ops = parse(EXAMPLE).unwrap();
-        let mut machine = Machine::new(ops);
-        machine.run();
+        let mut machine = Machine::<u64>::new(ops);
+        machine.run().unwrap();
         assert_eq!(machine[Reg::A], 2);
     }
+
+    /// Sets `a` to 3^41, which does not fit in a `u64`.
+    fn overflowing_program() -> Vec<Op> {
+        let source = std::iter::once("inc a")
+            .chain(std::iter::repeat_n("tpl a", 41))
+            .collect::<Vec<_>>()
+            .join("\n");
+        parse(&source).unwrap()
+    }
+
+    #[test]
+    fn test_checked_overflow() {
+        let mut machine = Machine::<u64>::new(overflowing_program());
+        let result = machine.run();
+        assert_eq!(
+            result,
+            Err(OverflowError {
+                ip: 41,
+                op: Op::Tpl(Reg::A)
+            })
+        );
+        assert_eq!(machine[Reg::A], 3u64.pow(40));
+    }
+
+    #[test]
+    fn test_wrapping_overflow() {
+        let mut machine = Machine::<Wrapping<u64>>::new(overflowing_program());
+        machine.run().unwrap();
+        assert_eq!(machine[Reg::A].0, 18_026_252_303_461_234_787);
+    }
+
+    #[test]
+    fn test_saturating_overflow() {
+        let mut machine = Machine::<Saturating<u64>>::new(overflowing_program());
+        machine.run().unwrap();
+        assert_eq!(machine[Reg::A].0, u64::MAX);
+    }
+
+    #[test]
+    fn test_overflow_runners() {
+        let source = std::iter::once("inc b")
+            .chain(std::iter::repeat_n("tpl b", 41))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let ops = parse(&source).unwrap();
+        assert!(part_1(&ops).is_err());
+        assert!(part_2(&ops).is_err());
+        assert_eq!(part_1_wrapping(&ops), Ok(18_026_252_303_461_234_787));
+        assert_eq!(part_2_wrapping(&ops), Ok(18_026_252_303_461_234_787));
+        assert_eq!(part_1_saturating(&ops), Ok(u64::MAX));
+        assert_eq!(part_2_saturating(&ops), Ok(u64::MAX));
+        assert_eq!(
+            part_1_arbitrary_precision(&ops).unwrap().to_string(),
+            "36472996377170786403"
+        );
+    }
+
+    #[test]
+    fn test_arbitrary_precision() {
+        let mut machine = Machine::<BigUint>::new(overflowing_program());
+        machine.run().unwrap();
+        assert_eq!(machine[Reg::A].to_string(), "36472996377170786403");
+        assert_eq!(BigUint::default().to_string(), "0");
+        assert_eq!(BigUint::from_u64(1_000_000_000).to_string(), "1000000000");
+    }
 }
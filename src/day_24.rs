@@ -1,116 +1,374 @@
+use std::fmt::Display;
 use std::num::ParseIntError;
 
+use thiserror::Error;
+
 #[aoc_generator(day24)]
 fn parse(input: &str) -> Result<Vec<u32>, ParseIntError> {
     input.lines().map(str::parse).collect()
 }
 
 #[aoc(day24, part1)]
-fn part_1(packages: &[u32]) -> u64 {
-    let sum: u32 = packages.iter().copied().sum();
-    let target = sum / 3;
-
-    let mut target_combinations = find_combinations_by_weight(packages, target);
-    target_combinations.sort_unstable_by_key(|&m| m.count_ones());
-
-    let mut minimum = (u32::MAX, u64::MAX); // (size, QE)
-    for &combination1 in &target_combinations {
-        let size1 = combination1.count_ones();
-        if size1 > minimum.0 {
-            break;
+fn part_1(packages: &[u32]) -> Result<u128, BalanceError> {
+    first_group_entanglement(packages, 3)
+}
+
+#[aoc(day24, part2)]
+fn part_2(packages: &[u32]) -> Result<u128, BalanceError> {
+    first_group_entanglement(packages, 4)
+}
+
+#[aoc(day24, part1, partition)]
+fn part_1_partition(packages: &[u32]) -> Result<String, BalanceError> {
+    describe_partition(packages, 3)
+}
+
+#[aoc(day24, part2, partition)]
+fn part_2_partition(packages: &[u32]) -> Result<String, BalanceError> {
+    describe_partition(packages, 4)
+}
+
+fn first_group_entanglement(packages: &[u32], group_count: usize) -> Result<u128, BalanceError> {
+    balance(packages, group_count)?
+        .quantum_entanglement()
+        .ok_or(BalanceError::EntanglementOverflow)
+}
+
+fn describe_partition(packages: &[u32], group_count: usize) -> Result<String, BalanceError> {
+    let partition = balance(packages, group_count)?;
+    partition.validate(packages)?;
+    Ok(partition.to_string())
+}
+
+/// Packages split into groups of equal weight. The first group goes in the passenger
+/// compartment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Partition {
+    groups: Vec<Vec<u32>>,
+}
+
+impl Partition {
+    /// Builds the partition from sets of package indices, one per group.
+    fn from_sets(packages: &[u32], sets: &[PackageSet]) -> Self {
+        let groups = sets
+            .iter()
+            .map(|set| set.select(packages).collect())
+            .collect();
+        Self { groups }
+    }
+
+    fn weights(&self) -> Vec<u32> {
+        self.groups.iter().map(|group| group.iter().sum()).collect()
+    }
+
+    /// The product of the weights in the first group, or `None` if it does not fit in a
+    /// `u128`.
+    fn quantum_entanglement(&self) -> Option<u128> {
+        self.groups
+            .first()
+            .map_or(Some(1), |group| product(group.iter().copied()))
+    }
+
+    /// Checks that the groups hold exactly `packages`, and all weigh the same.
+    fn validate(&self, packages: &[u32]) -> Result<(), PartitionError> {
+        let mut claimed = self.groups.concat();
+        let mut expected = packages.to_vec();
+        claimed.sort_unstable();
+        expected.sort_unstable();
+        if claimed != expected {
+            return Err(PartitionError::PackageMismatch);
         }
-        for &combination2 in &target_combinations {
-            if combination1 & combination2 != 0 {
-                continue;
-            }
-            // We have two non-overlapping combinations with 1/3 weight each. The third
-            // combination is the remaining packages, which must also have 1/3 weight.
-            //
-            // The QE is the product of the packages in `combination1`. Once we know
-            // there are at least one `combination2`, we do not need to look for more,
-            // and can skip to the next `combination1`.
-            let quantum_entanglement = packages
-                .iter()
-                .enumerate()
-                .filter_map(|(j, &package)| {
-                    ((combination1 & (1 << j)) != 0).then_some(u64::from(package))
-                })
-                .product();
-            minimum = minimum.min((size1, quantum_entanglement));
-            break;
+        let weights = self.weights();
+        let Some(&expected) = weights.first() else {
+            return Err(PartitionError::NoGroups);
+        };
+        if let Some((group, &weight)) = weights
+            .iter()
+            .enumerate()
+            .find(|&(_, &weight)| weight != expected)
+        {
+            return Err(PartitionError::UnequalWeight {
+                group,
+                weight,
+                expected,
+            });
         }
+        Ok(())
     }
-    minimum.1
 }
 
-#[aoc(day24, part2)]
-fn part_2(packages: &[u32]) -> u64 {
-    let sum: u32 = packages.iter().copied().sum();
-    let target = sum / 4;
+impl Display for Partition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (group, weight) in self.groups.iter().zip(self.weights()) {
+            let packages = group.iter().map(ToString::to_string).collect::<Vec<_>>();
+            writeln!(f)?;
+            write!(f, "{} (weight {weight})", packages.join(" "))?;
+        }
+        match self.quantum_entanglement() {
+            Some(entanglement) => write!(f, "\nQE {entanglement}"),
+            None => write!(f, "\nQE too large to compute"),
+        }
+    }
+}
 
-    let mut target_combinations = find_combinations_by_weight(packages, target);
-    target_combinations.sort_unstable_by_key(|&m| m.count_ones());
+#[derive(Debug, Error, PartialEq, Eq)]
+enum PartitionError {
+    #[error("The groups do not hold exactly the given packages")]
+    PackageMismatch,
+    #[error("There are no groups")]
+    NoGroups,
+    #[error("Group {group} weighs {weight}, expected {expected}")]
+    UnequalWeight {
+        group: usize,
+        weight: u32,
+        expected: u32,
+    },
+}
 
-    let mut minimum = (u32::MAX, u64::MAX); // (size, QE)
+#[derive(Debug, Error, PartialEq, Eq)]
+enum BalanceError {
+    #[error("Cannot split the packages into zero groups")]
+    NoGroups,
+    #[error(
+        "The total weight {total} cannot be split into {group_count} equal groups, \
+        leaving a remainder of {remainder}"
+    )]
+    Indivisible {
+        total: u32,
+        group_count: usize,
+        remainder: u32,
+    },
+    #[error("There is no way to split the packages into {group_count} groups weighing {target}")]
+    NoValidSubset { group_count: usize, target: u32 },
+    #[error("The quantum entanglement of the first group does not fit in 128 bits")]
+    EntanglementOverflow,
+    #[error(transparent)]
+    InvalidPartition(#[from] PartitionError),
+}
 
-    'next_combination1: for &combination1 in &target_combinations {
-        let size = combination1.count_ones();
-        if size > minimum.0 {
-            break;
+/// A set of package indices, growing a word at a time so it can hold any number of
+/// packages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct PackageSet {
+    words: Vec<u64>,
+}
+
+impl PackageSet {
+    fn insert(&mut self, index: usize) {
+        let (word, bit) = (index / 64, index % 64);
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
         }
-        for &combination2 in &target_combinations {
-            if combination1 & combination2 != 0 {
-                continue;
-            }
-            for &combination3 in &target_combinations {
-                if (combination1 | combination2) & combination3 != 0 {
-                    continue;
-                }
-                // We have three non-overlapping combinations with 1/4 weight each. The fourth
-                // combination is the remaining packages, which must also have 1/4 weight.
-                // The QE is the product of the packages in `combination1`. Once we know
-                // there are at least one `combination2` and `combination3`, we do not need
-                // to look for more, and can skip to the next `combination1`.
-                let quantum_entanglement = packages
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(package_index, &package)| {
-                        ((combination1 & (1 << package_index)) != 0).then_some(u64::from(package))
-                    })
-                    .product();
-                minimum = minimum.min((size, quantum_entanglement));
-                continue 'next_combination1;
-            }
+        self.words[word] |= 1 << bit;
+    }
+
+    fn remove(&mut self, index: usize) {
+        if let Some(word) = self.words.get_mut(index / 64) {
+            *word &= !(1 << (index % 64));
         }
     }
-    minimum.1
-}
-
-fn find_combinations_by_weight(packages: &[u32], target: u32) -> Vec<u32> {
-    let mut combinations_by_weight = vec![vec![]; target as usize + 1];
-    combinations_by_weight[0].push(0);
-
-    // Try to add each package to previous combiantions. Since it is a new package, we
-    // know it is not already included.
-    for (package_index, &package) in packages.iter().enumerate() {
-        let package_bit = 1u32 << package_index;
-        for total in (package..=target).rev() {
-            // Temporarily take out the Vec, so we can modify it while iterating over the other combinations.
-            let mut combinations_total =
-                std::mem::take(&mut combinations_by_weight[total as usize]);
-            for &combination in &combinations_by_weight[(total - package) as usize] {
-                combinations_total.push(combination | package_bit);
-            }
-            combinations_by_weight[total as usize] = combinations_total;
+
+    fn contains(&self, index: usize) -> bool {
+        self.words
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    /// The packages whose indices are in the set, in their original order.
+    fn select<'a>(&'a self, packages: &'a [u32]) -> impl Iterator<Item = u32> + 'a {
+        packages
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, &package)| self.contains(index).then_some(package))
+    }
+
+    fn quantum_entanglement(&self, packages: &[u32]) -> Option<u128> {
+        product(self.select(packages))
+    }
+}
+
+/// The product of the weights, or `None` if it does not fit in a `u128`.
+fn product(weights: impl IntoIterator<Item = u32>) -> Option<u128> {
+    weights.into_iter().try_fold(1, |product: u128, weight| {
+        product.checked_mul(weight.into())
+    })
+}
+
+/// Splits the packages into `group_count` groups of equal weight, such that the first
+/// group has as few packages as possible, and then the lowest quantum entanglement.
+///
+/// Candidates for the first group are generated one size at a time, so only groups
+/// that could be the answer are ever held in memory. Each is then checked, in order of
+/// quantum entanglement, for whether the rest of the packages can be balanced.
+fn balance(packages: &[u32], group_count: usize) -> Result<Partition, BalanceError> {
+    let total: u32 = packages.iter().copied().sum();
+    let count = u32::try_from(group_count)
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or(BalanceError::NoGroups)?;
+    if !total.is_multiple_of(count) {
+        return Err(BalanceError::Indivisible {
+            total,
+            group_count,
+            remainder: total % count,
+        });
+    }
+    let target = total / count;
+
+    // Heaviest first, so the searches below fill groups quickly and can bound the
+    // weight still reachable.
+    let mut order: Vec<usize> = (0..packages.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(packages[index]));
+
+    (1..=packages.len())
+        .find_map(|size| {
+            let mut candidates = groups_of_size(packages, &order, target, size);
+            // Groups whose entanglement overflows go last. They are only used when none
+            // of the others can be balanced, and then the answer cannot be computed
+            // anyway.
+            candidates.sort_by_cached_key(|set| {
+                set.quantum_entanglement(packages)
+                    .map_or((true, 0), |entanglement| (false, entanglement))
+            });
+            candidates.into_iter().find_map(|first| {
+                let rest: Vec<usize> = order
+                    .iter()
+                    .copied()
+                    .filter(|&index| !first.contains(index))
+                    .collect();
+                let mut groups = vec![first];
+                groups.extend(split_remaining(packages, &rest, target, group_count - 1)?);
+                Some(Partition::from_sets(packages, &groups))
+            })
+        })
+        .ok_or(BalanceError::NoValidSubset {
+            group_count,
+            target,
+        })
+}
+
+/// Every set of exactly `size` packages weighing `target`, found by branch and bound
+/// over the packages in `order`, heaviest first.
+fn groups_of_size(packages: &[u32], order: &[usize], target: u32, size: usize) -> Vec<PackageSet> {
+    // suffix_sums[i] is the weight of order[i..], so the heaviest `n` packages from
+    // position `i` weigh suffix_sums[i] - suffix_sums[i + n], and the lightest `n`
+    // weigh suffix_sums[order.len() - n].
+    let mut suffix_sums = vec![0; order.len() + 1];
+    for position in (0..order.len()).rev() {
+        suffix_sums[position] = suffix_sums[position + 1] + packages[order[position]];
+    }
+
+    let mut found = Vec::new();
+    search_groups(
+        packages,
+        order,
+        &suffix_sums,
+        0,
+        size,
+        target,
+        &mut PackageSet::default(),
+        &mut found,
+    );
+    found
+}
+
+#[allow(clippy::too_many_arguments, reason = "recursion state")]
+fn search_groups(
+    packages: &[u32],
+    order: &[usize],
+    suffix_sums: &[u32],
+    position: usize,
+    left: usize,
+    weight: u32,
+    set: &mut PackageSet,
+    found: &mut Vec<PackageSet>,
+) {
+    if left == 0 {
+        if weight == 0 {
+            found.push(set.clone());
         }
+        return;
+    }
+    let available = order.len() - position;
+    if available < left
+        || suffix_sums[position] - suffix_sums[position + left] < weight
+        || suffix_sums[order.len() - left] > weight
+    {
+        return;
+    }
+    let index = order[position];
+    if packages[index] <= weight {
+        set.insert(index);
+        search_groups(
+            packages,
+            order,
+            suffix_sums,
+            position + 1,
+            left - 1,
+            weight - packages[index],
+            set,
+            found,
+        );
+        set.remove(index);
     }
+    search_groups(
+        packages,
+        order,
+        suffix_sums,
+        position + 1,
+        left,
+        weight,
+        set,
+        found,
+    );
+}
+
+/// Splits the packages at the indices in `rest`, heaviest first, into `group_count`
+/// groups weighing `target` each, by placing each package in turn into a group it
+/// still fits in.
+fn split_remaining(
+    packages: &[u32],
+    rest: &[usize],
+    target: u32,
+    group_count: usize,
+) -> Option<Vec<PackageSet>> {
+    let mut groups = vec![PackageSet::default(); group_count];
+    let mut loads = vec![0; group_count];
+    place_package(packages, rest, target, &mut groups, &mut loads).then_some(groups)
+}
 
-    combinations_by_weight.swap_remove(target as usize)
+fn place_package(
+    packages: &[u32],
+    rest: &[usize],
+    target: u32,
+    groups: &mut [PackageSet],
+    loads: &mut [u32],
+) -> bool {
+    let Some((&index, rest)) = rest.split_first() else {
+        return loads.iter().all(|&load| load == target);
+    };
+    let package = packages[index];
+    for group in 0..groups.len() {
+        // Groups with the same load are interchangeable, so only the first of them is
+        // worth trying.
+        if loads[group] + package > target || loads[..group].contains(&loads[group]) {
+            continue;
+        }
+        groups[group].insert(index);
+        loads[group] += package;
+        if place_package(packages, rest, target, groups, loads) {
+            return true;
+        }
+        groups[group].remove(index);
+        loads[group] -= package;
+    }
+    false
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_case::test_case;
 
     const EXAMPLE: &str = "\
         1\n\
@@ -129,13 +387,120 @@ mod tests {
     fn test_part_1() {
         let nums = parse(EXAMPLE).unwrap();
         let result = part_1(&nums);
-        assert_eq!(result, 99);
+        assert_eq!(result, Ok(99));
     }
 
     #[test]
     fn test_part_2() {
         let nums = parse(EXAMPLE).unwrap();
         let result = part_2(&nums);
-        assert_eq!(result, 44);
+        assert_eq!(result, Ok(44));
+    }
+
+    #[test]
+    fn test_partition() {
+        let nums = parse(EXAMPLE).unwrap();
+        let partition = balance(&nums, 3).unwrap();
+        assert_eq!(partition.groups[0], [9, 11]);
+        assert_eq!(partition.weights(), [20, 20, 20]);
+        assert_eq!(partition.quantum_entanglement(), Some(99));
+        assert_eq!(partition.validate(&nums), Ok(()));
+
+        let partition = balance(&nums, 4).unwrap();
+        assert_eq!(partition.groups[0], [4, 11]);
+        assert_eq!(partition.weights(), [15, 15, 15, 15]);
+        assert_eq!(partition.validate(&nums), Ok(()));
+    }
+
+    #[test_case(1 => Ok(vec![vec![1, 2, 3, 4, 5, 7, 8, 9, 10, 11]]))]
+    #[test_case(2 => Ok(vec![vec![9, 10, 11], vec![1, 2, 3, 4, 5, 7, 8]]))]
+    #[test_case(5 => Ok(vec![vec![1, 11], vec![2, 10], vec![3, 9], vec![4, 8], vec![5, 7]]))]
+    #[test_case(6 => Err(BalanceError::NoValidSubset { group_count: 6, target: 10 }))]
+    #[test_case(7 => Err(BalanceError::Indivisible { total: 60, group_count: 7, remainder: 4 }))]
+    #[test_case(0 => Err(BalanceError::NoGroups))]
+    fn test_balance(group_count: usize) -> Result<Vec<Vec<u32>>, BalanceError> {
+        let nums = parse(EXAMPLE).unwrap();
+        let partition = balance(&nums, group_count)?;
+        assert_eq!(partition.validate(&nums), Ok(()));
+        Ok(partition.groups)
+    }
+
+    #[test_case(&[1, 2, 3, 4, 5] => Err(BalanceError::Indivisible { total: 15, group_count: 4, remainder: 3 }))]
+    #[test_case(&[1, 1, 1, 1, 1, 7] => Err(BalanceError::NoValidSubset { group_count: 4, target: 3 }))]
+    #[test_case(&[4, 4, 4, 3, 3, 3, 3] => Err(BalanceError::NoValidSubset { group_count: 4, target: 6 }); "no split of the rest")]
+    #[test_case(&[] => Err(BalanceError::NoValidSubset { group_count: 4, target: 0 }); "no packages")]
+    fn test_part_2_errors(nums: &[u32]) -> Result<u128, BalanceError> {
+        part_2(nums)
+    }
+
+    #[test]
+    fn test_backtracking() {
+        // The best group of weight 16, [1, 4, 11], leaves packages that cannot be split
+        // evenly, so the next best has to be used.
+        let nums = [1, 2, 3, 3, 4, 6, 9, 9, 11];
+        let partition = balance(&nums, 3).unwrap();
+        assert_eq!(partition.validate(&nums), Ok(()));
+        assert_eq!(partition.groups[0], [1, 6, 9]);
+    }
+
+    #[test]
+    fn test_many_packages() {
+        // More packages than fit in a single word of the bit set.
+        let nums: Vec<u32> = (1..=64).chain([500, 600, 700, 800]).collect();
+        let partition = balance(&nums, 3).unwrap();
+        assert_eq!(partition.validate(&nums), Ok(()));
+        assert_eq!(partition.groups[0], [60, 700, 800]);
+        assert_eq!(part_1(&nums), Ok(33_600_000));
+
+        let partition = balance(&nums, 4).unwrap();
+        assert_eq!(partition.validate(&nums), Ok(()));
+        assert_eq!(partition.groups[0], [6, 64, 500, 600]);
+        assert_eq!(part_2(&nums), Ok(115_200_000));
+    }
+
+    #[test]
+    fn test_large_first_group() {
+        // The first group needs 18 packages, whose product does not fit in a `u64`.
+        const QE_1_TO_96: u128 = 62_075_602_241_190_697_006_979_859_087_360_000;
+        let nums: Vec<u32> = (1..=96).collect();
+        let partition = balance(&nums, 3).unwrap();
+        assert_eq!(partition.validate(&nums), Ok(()));
+        assert_eq!(partition.groups[0].len(), 18);
+        assert_eq!(part_1(&nums), Ok(QE_1_TO_96));
+        assert!(partition.to_string().ends_with(&format!("QE {QE_1_TO_96}")));
+    }
+
+    #[test]
+    fn test_entanglement_overflow() {
+        // The product of any group of five packages is 2^130.
+        let nums = [1 << 26; 20];
+        assert_eq!(part_2(&nums), Err(BalanceError::EntanglementOverflow));
+        let partition = balance(&nums, 4).unwrap();
+        assert!(partition.to_string().ends_with("QE too large to compute"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let nums = parse(EXAMPLE).unwrap();
+        let partition = |groups: &[&[u32]]| Partition {
+            groups: groups.iter().map(|group| group.to_vec()).collect(),
+        };
+        assert_eq!(
+            partition(&[&[11, 9], &[10, 8, 2], &[7, 5, 4, 3, 1]]).validate(&nums),
+            Ok(())
+        );
+        assert_eq!(
+            partition(&[&[11, 9], &[10, 8, 2], &[7, 5, 4, 3]]).validate(&nums),
+            Err(PartitionError::PackageMismatch)
+        );
+        assert_eq!(
+            partition(&[&[11, 9], &[10, 8, 3], &[7, 5, 4, 2, 1]]).validate(&nums),
+            Err(PartitionError::UnequalWeight {
+                group: 1,
+                weight: 21,
+                expected: 20
+            })
+        );
+        assert_eq!(partition(&[]).validate(&[]), Err(PartitionError::NoGroups));
     }
 }
@@ -33,4 +33,7 @@ mod day_25;
 
 mod utils;
 
+pub use day_12::stream_sum as sum_day_12_stream;
+pub use day_22::play as play_day_22;
+
 aoc_lib! { year = 2015 }